@@ -0,0 +1,217 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use std::collections::HashSet;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, Ident, LitStr, Token, Variant};
+
+/// Pairs every variant `Foo` of a Maelstrom message enum with its `FooOk`
+/// response.
+///
+/// For an enum `EchoMessage` this generates:
+///
+/// - `EchoRequest` (the `Message` suffix is replaced, otherwise `Request` is
+///   appended): the same enum without the response variants, deserializable
+///   with the same `#[serde]` attributes. Handlers take this type, so a
+///   response can never reach them.
+/// - a constructor per response, e.g. `EchoMessage::echo_ok(echo)`.
+/// - `Protocol` for the enum and `Inbound` for the request enum, used by
+///   `Sender::reply_to` to check that a reply answers its request.
+///
+/// Variants without an `Ok` counterpart are treated as requests.
+#[proc_macro_derive(MaelstromProtocol)]
+pub fn derive_maelstrom_protocol(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            input,
+            "MaelstromProtocol can only be derived for enums",
+        ));
+    };
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "MaelstromProtocol does not support generic enums",
+        ));
+    }
+
+    let name = &input.ident;
+    let vis = &input.vis;
+    let request = request_name(name);
+    let serde_attrs: Vec<&Attribute> = input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("serde"))
+        .collect();
+    let rename_all = rename_all(&input.attrs)?;
+
+    let names: HashSet<String> = data.variants.iter().map(|v| v.ident.to_string()).collect();
+    let request_of = |variant: &Variant| {
+        variant
+            .ident
+            .to_string()
+            .strip_suffix("Ok")
+            .filter(|prefix| names.contains(*prefix))
+            .map(|prefix| format_ident!("{}", prefix))
+    };
+
+    let mut requests = Vec::new();
+    let mut request_types = Vec::new();
+    let mut constructors = Vec::new();
+    let mut answers = Vec::new();
+    for variant in &data.variants {
+        let Some(paired) = request_of(variant) else {
+            requests.push(variant);
+            request_types.push(wire_name(variant, rename_all.as_deref())?);
+            continue;
+        };
+
+        let response = &variant.ident;
+        let doc = format!(
+            " Builds the `{}` reply to a `{}` request",
+            wire_name(variant, rename_all.as_deref())?,
+            snake_case(&paired.to_string()),
+        );
+        let constructor = format_ident!("{}", snake_case(&response.to_string()));
+        let (params, build) = match &variant.fields {
+            Fields::Named(fields) => {
+                let idents: Vec<_> = fields.named.iter().map(|f| &f.ident).collect();
+                let types: Vec<_> = fields.named.iter().map(|f| &f.ty).collect();
+                (
+                    quote! { #(#idents: #types),* },
+                    quote! { Self::#response { #(#idents),* } },
+                )
+            }
+            Fields::Unnamed(fields) => {
+                let idents: Vec<_> = (0..fields.unnamed.len())
+                    .map(|i| format_ident!("_{}", i))
+                    .collect();
+                let types: Vec<_> = fields.unnamed.iter().map(|f| &f.ty).collect();
+                (
+                    quote! { #(#idents: #types),* },
+                    quote! { Self::#response(#(#idents),*) },
+                )
+            }
+            Fields::Unit => (quote! {}, quote! { Self::#response }),
+        };
+        constructors.push(quote! {
+            #[doc = #doc]
+            #[must_use]
+            pub fn #constructor(#params) -> Self {
+                #build
+            }
+        });
+
+        let paired_variant = data
+            .variants
+            .iter()
+            .find(|v| v.ident == paired)
+            .expect("paired variant exists");
+        let response_pattern = pattern(quote! { Self }, variant);
+        let request_pattern = pattern(quote! { #request }, paired_variant);
+        answers.push(quote! { (#response_pattern, #request_pattern) => true });
+    }
+
+    Ok(quote! {
+        #[derive(::std::clone::Clone, ::serde::Deserialize)]
+        #(#serde_attrs)*
+        #vis enum #request {
+            #(#requests),*
+        }
+
+        #[allow(dead_code)]
+        impl #name {
+            #(#constructors)*
+        }
+
+        impl ::symmetrical_octo_potato::protocol::Protocol for #name {
+            type Request = #request;
+
+            fn answers(&self, request: &Self::Request) -> bool {
+                #[allow(unreachable_patterns)]
+                match (self, request) {
+                    #(#answers,)*
+                    _ => false,
+                }
+            }
+        }
+
+        impl ::symmetrical_octo_potato::protocol::Inbound for #request {
+            const TYPES: &'static [&'static str] = &[#(#request_types),*];
+        }
+    })
+}
+
+fn request_name(name: &Ident) -> Ident {
+    let name = name.to_string();
+    let base = name.strip_suffix("Message").unwrap_or(&name);
+    format_ident!("{}Request", base)
+}
+
+fn pattern(path: TokenStream2, variant: &Variant) -> TokenStream2 {
+    let ident = &variant.ident;
+    match variant.fields {
+        Fields::Named(_) => quote! { #path::#ident { .. } },
+        Fields::Unnamed(_) => quote! { #path::#ident(..) },
+        Fields::Unit => quote! { #path::#ident },
+    }
+}
+
+/// Reads `#[serde(rename_all = "...")]` from the enum
+fn rename_all(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    serde_value(attrs, "rename_all")
+}
+
+/// The `type` a variant has on the wire
+fn wire_name(variant: &Variant, rename_all: Option<&str>) -> syn::Result<String> {
+    if let Some(rename) = serde_value(&variant.attrs, "rename")? {
+        return Ok(rename);
+    }
+    let ident = variant.ident.to_string();
+    match rename_all {
+        None => Ok(ident),
+        Some("snake_case") => Ok(snake_case(&ident)),
+        Some("lowercase") => Ok(ident.to_lowercase()),
+        Some(other) => Err(syn::Error::new_spanned(
+            variant,
+            format!("MaelstromProtocol does not support rename_all = \"{other}\""),
+        )),
+    }
+}
+
+fn serde_value(attrs: &[Attribute], key: &str) -> syn::Result<Option<String>> {
+    let mut value = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(key) {
+                value = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.input.peek(Token![=]) {
+                meta.value()?.parse::<syn::Expr>()?;
+            } else if !meta.input.is_empty() && !meta.input.peek(Token![,]) {
+                meta.parse_nested_meta(|_| Ok(()))?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(value)
+}
+
+fn snake_case(ident: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in ident.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
@@ -1,31 +1,37 @@
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashSet,
     ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
     time::Duration,
 };
 use symmetrical_octo_potato::{
-    gossip,
-    init_state::{init_parser, Init, InitState, Initable},
+    init_state::{Init, InitState, Initable},
     log::Log,
     message::Message,
-    sender::Sender,
+    protocol::MaelstromProtocol,
+    sender::{Batching, Sender},
     stdout_writer::StdOutWriter,
+    topology::{self, Graph, Topology},
     traits::store::Store,
-    wait_for_message_then,
+    Node,
 };
-use tracing_subscriber::{fmt, prelude::*};
 
 struct BroadcastState {
     messages: Log<usize>,
+    topology: Box<dyn Topology>,
 }
 
 impl Initable for BroadcastState {
     fn with_init(init: Init) -> Self {
+        let topology = topology::from_env().unwrap_or_else(|error| {
+            tracing::warn!(%error, "Using the topology suggested by Maelstrom");
+            Box::new(topology::Maelstrom)
+        });
         Self {
             messages: Log::with_init(init),
+            topology,
         }
     }
 }
@@ -49,99 +55,76 @@ impl Store<usize> for BroadcastState {
     }
 }
 
+/// Picks how messages spread between nodes: `gossip` (the default) or
+/// `plumtree`
+const REPLICATION: &str = "REPLICATION";
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let layer = fmt::layer()
-        .with_writer(std::io::stderr)
-        .with_thread_ids(true)
-        .with_ansi(false)
-        .pretty();
-    tracing_subscriber::registry().with(layer).init();
-    let (tx, mut rx) = tokio::sync::broadcast::channel(16);
-    let output = Arc::new(Mutex::new(Sender::default()));
-    symmetrical_octo_potato::init_stdin(tx.clone());
-    let state = init_parser::<BroadcastState, StdOutWriter>(tx.subscribe(), output.clone()).await?;
-
-    let tx_clone = tx.clone();
-    let output_clone = output.clone();
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-        gossip::handle(
-            tx_clone.subscribe(),
-            output_clone,
-            state_clone,
-            Duration::from_millis(100),
-        )
-        .await;
+    let node = Node::<BroadcastState>::new().with_batching(Batching {
+        max_size: 64,
+        max_delay: Duration::from_millis(5),
     });
-
-    let _ = wait_for_message_then(&mut rx, |msg| handle_message(msg, &output, &state)).await;
-    Ok(())
+    let periodicity = Duration::from_millis(100);
+    let node = match std::env::var(REPLICATION).as_deref() {
+        Ok("gossip") | Err(_) => node.with_gossip(periodicity),
+        Ok("plumtree") => node.with_plumtree(periodicity),
+        Ok(other) => bail!("Unknown replication {other:?}"),
+    };
+    node.run(handle_message).await
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum BroadcastMessage {
-    Broadcast {
-        message: usize,
-    },
+    Broadcast { message: usize },
     BroadcastOk,
     Read,
-    ReadOk {
-        messages: HashSet<usize>,
-    },
-    Topology {
-        topology: HashMap<String, Vec<String>>,
-    },
+    ReadOk { messages: HashSet<usize> },
+    Topology { topology: Graph },
     TopologyOk,
 }
 
 fn handle_message(
-    input: Message<BroadcastMessage>,
+    input: &Message<BroadcastRequest>,
     output: &Arc<Mutex<Sender<StdOutWriter>>>,
     state: &Arc<Mutex<InitState<BroadcastState>>>,
 ) -> Result<()> {
     match input.body.msg_type {
-        BroadcastMessage::Broadcast { message } => {
+        BroadcastRequest::Broadcast { message } => {
             let _ = output
                 .lock()
                 .unwrap()
-                .reply(input.clone(), BroadcastMessage::BroadcastOk);
+                .reply_to(input, BroadcastMessage::broadcast_ok());
 
             let mut state = state.lock().unwrap();
             if let Some(val) = state.messages.insert(&message) {
                 state.new_value(val);
             }
         }
-        BroadcastMessage::Read => {
+        BroadcastRequest::Read => {
             let messages = state.lock().unwrap().messages.values().copied().collect();
             let _ = output
                 .lock()
                 .unwrap()
-                .reply(input, BroadcastMessage::ReadOk { messages });
+                .reply_to(input, BroadcastMessage::read_ok(messages));
         }
-        BroadcastMessage::Topology { ref topology } => {
+        BroadcastRequest::Topology { ref topology } => {
             let _ = output
                 .lock()
                 .unwrap()
-                .reply(input.clone(), BroadcastMessage::TopologyOk);
+                .reply_to(input, BroadcastMessage::topology_ok());
 
             let mut state = state.lock().unwrap();
-            let node = state.get_init().node_id.clone();
-            topology
-                .get(&node)
-                .expect("We are a node")
-                .clone()
-                .iter()
-                .for_each(|n| {
-                    state.update_neighbor(n);
-                });
-        }
-        BroadcastMessage::ReadOk { messages: _ }
-        | BroadcastMessage::TopologyOk
-        | BroadcastMessage::BroadcastOk => {
-            bail!("This is not expected");
+            let init = state.get_init().clone();
+            let neighbors = state
+                .topology
+                .graph(&init.node_ids, topology)
+                .remove(&init.node_id)
+                .unwrap_or_default();
+            tracing::info!(?neighbors, "Topology");
+            state.set_neighbors(neighbors);
         }
     };
     Ok(())
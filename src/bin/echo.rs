@@ -1,14 +1,14 @@
-use anyhow::{bail, Result};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use symmetrical_octo_potato::{
-    init_state::{init_parser, Init, Initable},
+    init_state::{Init, InitState, Initable},
     message::Message,
+    protocol::MaelstromProtocol,
     sender::Sender,
     stdout_writer::StdOutWriter,
-    wait_for_message_then,
+    Node,
 };
-use tracing_subscriber::{fmt, prelude::*};
 
 struct EchoState {}
 
@@ -20,21 +20,10 @@ impl Initable for EchoState {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let layer = fmt::layer()
-        .with_writer(std::io::stderr)
-        .with_thread_ids(true)
-        .with_ansi(false)
-        .pretty();
-    tracing_subscriber::registry().with(layer).init();
-    let (tx, mut rx) = tokio::sync::broadcast::channel(16);
-    let output = Arc::new(Mutex::new(Sender::default()));
-    symmetrical_octo_potato::init_stdin(tx.clone());
-    let _ = init_parser::<EchoState, StdOutWriter>(tx.subscribe(), output.clone()).await?;
-    let _ = wait_for_message_then(&mut rx, |msg| handle_message(&msg, &output)).await;
-    Ok(())
+    Node::<EchoState>::new().run(handle_message).await
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum EchoMessage {
@@ -43,20 +32,16 @@ enum EchoMessage {
 }
 
 fn handle_message(
-    input: &Message<EchoMessage>,
+    input: &Message<EchoRequest>,
     output: &Arc<Mutex<Sender<StdOutWriter>>>,
+    _state: &Arc<Mutex<InitState<EchoState>>>,
 ) -> Result<()> {
     match input.body.msg_type {
-        EchoMessage::Echo { ref echo } => {
-            let _ = output.lock().unwrap().reply(
-                input.clone(),
-                EchoMessage::EchoOk {
-                    echo: echo.to_string(),
-                },
-            );
-        }
-        EchoMessage::EchoOk { .. } => {
-            bail!("do not handle this")
+        EchoRequest::Echo { ref echo } => {
+            let _ = output
+                .lock()
+                .unwrap()
+                .reply_to(input, EchoMessage::echo_ok(echo.to_string()));
         }
     };
     Ok(())
@@ -1,123 +1,194 @@
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 use std::{
-    ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
     time::Duration,
 };
 use symmetrical_octo_potato::{
-    gossip,
-    init_state::{init_parser, Init, InitState, Initable},
-    log::Log,
+    crdt::{Crdt, GCounter, Map},
+    init_state::{Init, InitState, Initable},
+    kv::{KvError, SeqKv},
     message::Message,
+    protocol::MaelstromProtocol,
     sender::Sender,
     stdout_writer::StdOutWriter,
-    traits::store::Store,
-    wait_for_message_then,
+    traits::replica::Replica,
+    Node,
 };
-use tracing_subscriber::{fmt, prelude::*};
 
 struct GrowOnlyState {
-    operations: Log<usize>,
-}
-
-impl Store<usize> for GrowOnlyState {
-    fn new_value(&mut self, new: &usize) {
-        tracing::info!(new = %new, "received");
-    }
+    /// Counters by name, `None` being the one adds and reads without a key
+    /// go to
+    counters: Map<Option<String>, GCounter>,
+    /// Reads served from `seq-kv`, which numbers their writes
+    reads: usize,
 }
 
 impl Initable for GrowOnlyState {
-    fn with_init(init: Init) -> Self {
+    fn with_init(_: Init) -> Self {
         Self {
-            operations: Log::with_init(init),
+            counters: Map::default(),
+            reads: 0,
         }
     }
 }
 
-impl Deref for GrowOnlyState {
-    type Target = Log<usize>;
+impl Replica for GrowOnlyState {
+    type Crdt = Map<Option<String>, GCounter>;
 
-    fn deref(&self) -> &Self::Target {
-        &self.operations
+    fn crdt(&self) -> &Self::Crdt {
+        &self.counters
     }
-}
-impl DerefMut for GrowOnlyState {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.operations
+
+    fn crdt_mut(&mut self) -> &mut Self::Crdt {
+        &mut self.counters
     }
 }
 
+/// Picks where counters live: `gossip` (the default) replicates them between
+/// nodes, `seq-kv` keeps their sums in Maelstrom's `seq-kv`
+const BACKEND: &str = "COUNTER_BACKEND";
+
+/// Key of the sum of the counter without a name in `seq-kv`, named ones are
+/// kept under `counter/<name>`
+const COUNTER_KEY: &str = "counter";
+
+#[derive(Clone, Copy)]
+enum Backend {
+    Gossip,
+    SeqKv,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let layer = fmt::layer()
-        .with_writer(std::io::stderr)
-        .with_thread_ids(true)
-        .with_ansi(false)
-        .pretty();
-    tracing_subscriber::registry().with(layer).init();
-    let (tx, mut rx) = tokio::sync::broadcast::channel(16);
-    let output = Arc::new(Mutex::new(Sender::default()));
-    symmetrical_octo_potato::init_stdin(tx.clone());
-    let state = init_parser::<GrowOnlyState, StdOutWriter>(tx.subscribe(), output.clone()).await?;
-
-    let tx_clone = tx.clone();
-    let output_clone = output.clone();
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-        gossip::handle(
-            tx_clone.subscribe(),
-            output_clone,
-            state_clone,
-            Duration::from_millis(100),
-        )
-        .await;
-    });
-
-    let _ = wait_for_message_then(&mut rx, |msg| handle_message(&msg, &output, &state)).await;
-    Ok(())
+    let backend = match std::env::var(BACKEND).as_deref() {
+        Ok("gossip") | Err(_) => Backend::Gossip,
+        Ok("seq-kv") => Backend::SeqKv,
+        Ok(other) => bail!("Unknown backend {other:?}"),
+    };
+    let node = Node::<GrowOnlyState>::new();
+    let node = match backend {
+        Backend::Gossip => node.with_gossip(Duration::from_millis(100)),
+        Backend::SeqKv => node,
+    };
+    node.run(move |input, output, state| match backend {
+        Backend::Gossip => handle_message(input, output, state),
+        Backend::SeqKv => handle_seq_kv(input, output, state),
+    })
+    .await
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum GrowOnlyMessage {
-    Add { delta: usize },
+    Add { delta: usize, key: Option<String> },
     Read { key: Option<String> },
     AddOk,
     ReadOk { value: usize },
 }
 
 fn handle_message(
-    input: &Message<GrowOnlyMessage>,
+    input: &Message<GrowOnlyRequest>,
     output: &Arc<Mutex<Sender<StdOutWriter>>>,
     state: &Arc<Mutex<InitState<GrowOnlyState>>>,
 ) -> Result<()> {
     match input.body.msg_type {
-        GrowOnlyMessage::Add { delta } => {
+        GrowOnlyRequest::Add { delta, ref key } => {
+            let mut state = state.lock().unwrap();
+            let node = state.get_init().node_id.clone();
+            state.counters.entry(key.clone()).increment(&node, delta);
+            std::mem::drop(state);
+
             let _ = output
                 .lock()
                 .unwrap()
-                .reply(input.clone(), GrowOnlyMessage::AddOk);
-
-            let mut state = state.lock().unwrap();
-            if let Some(val) = state.operations.insert(&delta) {
-                state.new_value(val);
-            }
-        }
-        GrowOnlyMessage::Read { key: None } => {
-            let _ = output.lock().unwrap().reply(
-                input.clone(),
-                GrowOnlyMessage::ReadOk {
-                    value: state.lock().unwrap().operations.values().sum::<usize>(),
-                },
-            );
+                .reply_to(input, GrowOnlyMessage::add_ok());
         }
-        GrowOnlyMessage::Read { key: Some(_) }
-        | GrowOnlyMessage::AddOk
-        | GrowOnlyMessage::ReadOk { value: _ } => {
-            bail!("Not handled")
+        GrowOnlyRequest::Read { ref key } => {
+            let value = state
+                .lock()
+                .unwrap()
+                .counters
+                .get(key)
+                .map(Crdt::value)
+                .unwrap_or_default();
+            let _ = output
+                .lock()
+                .unwrap()
+                .reply_to(input, GrowOnlyMessage::read_ok(value));
         }
     };
     Ok(())
 }
+
+/// Answers from `seq-kv` in a task of its own, as it takes round trips
+fn handle_seq_kv(
+    input: &Message<GrowOnlyRequest>,
+    output: &Arc<Mutex<Sender<StdOutWriter>>>,
+    state: &Arc<Mutex<InitState<GrowOnlyState>>>,
+) -> Result<()> {
+    let mut state = state.lock().unwrap();
+    let node = state.get_init().node_id.clone();
+    state.reads += 1;
+    let read_id = state.reads;
+    std::mem::drop(state);
+
+    let kv = SeqKv::new(output.clone());
+    let input = input.clone();
+    let output = output.clone();
+    tokio::spawn(async move {
+        let reply = match input.body.msg_type {
+            GrowOnlyRequest::Add { delta, ref key } => add(&kv, &counter_key(key), delta)
+                .await
+                .map(|()| GrowOnlyMessage::add_ok()),
+            GrowOnlyRequest::Read { ref key } => read(&kv, &counter_key(key), &node, read_id)
+                .await
+                .map(GrowOnlyMessage::read_ok),
+        };
+        let mut output = output.lock().unwrap();
+        let _ = match reply {
+            Ok(reply) => output.reply_to(&input, reply),
+            Err(error) => output.reply_error(&input, error.into()),
+        };
+    });
+    Ok(())
+}
+
+fn counter_key(key: &Option<String>) -> String {
+    match key {
+        None => COUNTER_KEY.to_string(),
+        Some(name) => format!("{COUNTER_KEY}/{name}"),
+    }
+}
+
+/// Adds `delta` to the sum at `counter`, trying again as long as another
+/// node changed it in between
+async fn add(kv: &SeqKv<StdOutWriter>, counter: &str, delta: usize) -> Result<(), KvError> {
+    loop {
+        let current = match kv.read::<usize>(counter).await {
+            Ok(current) => current,
+            Err(KvError::KeyDoesNotExist) => 0,
+            Err(error) => return Err(error),
+        };
+        match kv.cas(counter, current, current + delta, true).await {
+            Err(KvError::PreconditionFailed) => continue,
+            result => return result,
+        }
+    }
+}
+
+/// `seq-kv` may serve stale reads, but not older than this node's own
+/// writes: writing a value never written before brings the read up to date
+async fn read(
+    kv: &SeqKv<StdOutWriter>,
+    counter: &str,
+    node: &str,
+    read_id: usize,
+) -> Result<usize, KvError> {
+    kv.write(node, read_id).await?;
+    match kv.read(counter).await {
+        Err(KvError::KeyDoesNotExist) => Ok(0),
+        result => result,
+    }
+}
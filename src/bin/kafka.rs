@@ -1,30 +1,127 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap},
     ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
     time::Duration,
 };
 use symmetrical_octo_potato::{
-    gossip,
-    init_state::{init_parser, Init, InitState, Initable},
+    init_state::{Init, InitState, Initable},
+    kv::{KvError, LinKv},
     log::Log,
     message::Message,
+    protocol::MaelstromProtocol,
     sender::Sender,
     stdout_writer::StdOutWriter,
     traits::store::Store,
-    wait_for_message_then,
+    Node,
 };
-use tracing_subscriber::{fmt, prelude::*};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum KafkaOperation {
+    Send {
+        key: String,
+        offset: usize,
+        msg: usize,
+    },
+    /// Nothing will ever be stored at `offset`, see [`settle`]
+    Skip {
+        key: String,
+        offset: usize,
+    },
+    Commit {
+        offsets: HashMap<String, usize>,
+    },
+}
 
 struct KafkaState {
-    operations: Log<HashMap<String, usize>>,
+    operations: Log<KafkaOperation>,
+    /// `None` at skipped offsets
+    messages: HashMap<String, BTreeMap<usize, Option<usize>>>,
+    committed: HashMap<String, usize>,
+}
+
+impl KafkaState {
+    /// Polls stop at the first offset this node knows nothing about: its
+    /// message may still be on its way, and returning the ones after it would
+    /// make the consumer skip it. Skipped offsets are stepped over.
+    fn poll(&self, offsets: &HashMap<String, usize>) -> HashMap<String, Vec<[usize; 2]>> {
+        offsets
+            .iter()
+            .filter_map(|(key, from)| {
+                let messages = self.messages.get(key)?;
+                Some((
+                    key.clone(),
+                    messages
+                        .range(from..)
+                        .zip(*from..)
+                        .take_while(|((offset, _), expected)| **offset == *expected)
+                        .filter_map(|((offset, msg), _)| Some([*offset, (*msg)?]))
+                        .collect(),
+                ))
+            })
+            .collect()
+    }
+
+    /// Offsets of the polled keys this node knows nothing about, below the
+    /// highest one it knows: polls would stop there until they are settled
+    fn unsettled(&self, offsets: &HashMap<String, usize>) -> Vec<(String, usize)> {
+        offsets
+            .iter()
+            .filter_map(|(key, from)| Some((key, *from, self.messages.get(key)?)))
+            .flat_map(|(key, from, messages)| {
+                let last = messages
+                    .last_key_value()
+                    .map_or(from, |(offset, _)| *offset);
+                (from..last)
+                    .filter(|offset| !messages.contains_key(offset))
+                    .map(|offset| (key.clone(), offset))
+            })
+            .collect()
+    }
+
+    fn record(&mut self, operation: &KafkaOperation) {
+        if let Some(val) = self.operations.insert(operation) {
+            self.new_value(val);
+        }
+    }
+
+    fn committed_offsets<'a>(
+        &self,
+        keys: impl Iterator<Item = &'a String>,
+    ) -> HashMap<String, usize> {
+        keys.filter_map(|key| Some((key.clone(), *self.committed.get(key)?)))
+            .collect()
+    }
 }
 
-impl Store<HashMap<String, usize>> for KafkaState {
-    fn new_value(&mut self, new: &HashMap<String, usize>) {
+impl Store<KafkaOperation> for KafkaState {
+    fn new_value(&mut self, new: &KafkaOperation) {
         tracing::info!(new = ?new, "received");
+        match new {
+            KafkaOperation::Send { key, offset, msg } => {
+                self.messages
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(*offset, Some(*msg));
+            }
+            KafkaOperation::Skip { key, offset } => {
+                self.messages
+                    .entry(key.clone())
+                    .or_default()
+                    .entry(*offset)
+                    .or_insert(None);
+            }
+            KafkaOperation::Commit { offsets } => {
+                for (key, offset) in offsets {
+                    let committed = self.committed.entry(key.clone()).or_default();
+                    *committed = (*committed).max(*offset);
+                }
+            }
+        }
     }
 }
 
@@ -32,12 +129,14 @@ impl Initable for KafkaState {
     fn with_init(init: Init) -> Self {
         Self {
             operations: Log::with_init(init),
+            messages: HashMap::new(),
+            committed: HashMap::new(),
         }
     }
 }
 
 impl Deref for KafkaState {
-    type Target = Log<HashMap<String, usize>>;
+    type Target = Log<KafkaOperation>;
 
     fn deref(&self) -> &Self::Target {
         &self.operations
@@ -49,40 +148,21 @@ impl DerefMut for KafkaState {
     }
 }
 
+/// Prefix of the `lin-kv` keys holding the next offset of each key
+const OFFSETS_KEY: &str = "next_offset";
+/// Prefix of the `lin-kv` keys holding what each offset stores, `null` once
+/// skipped
+const SLOTS_KEY: &str = "slot";
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let layer = fmt::layer()
-        .with_writer(std::io::stderr)
-        .with_thread_ids(true)
-        .with_ansi(false)
-        .pretty();
-    tracing_subscriber::registry().with(layer).init();
-    let (tx, mut rx) = tokio::sync::broadcast::channel(16);
-    let output = Arc::new(Mutex::new(Sender::default()));
-    symmetrical_octo_potato::init_stdin(tx.clone());
-    let state = init_parser::<KafkaState, StdOutWriter>(tx.subscribe(), output.clone()).await?;
-    let tx_clone = tx.clone();
-    let output_clone = output.clone();
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-        gossip::handle(
-            tx_clone.subscribe(),
-            output_clone,
-            state_clone,
-            Duration::from_millis(100),
-        )
-        .await;
-    });
-
-    let _ = wait_for_message_then(&mut rx, |msg| {
-        handle_message(&msg, &output, &state);
-        Ok(())
-    })
-    .await;
-    Ok(())
+    Node::<KafkaState>::new()
+        .with_gossip(Duration::from_millis(100))
+        .run(handle_message)
+        .await
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum KafkaMessage {
@@ -96,15 +176,15 @@ enum KafkaMessage {
     Poll {
         offsets: HashMap<String, usize>,
     },
-    PoolOk {
-        msgs: HashMap<String, HashSet<(usize, usize)>>,
+    PollOk {
+        msgs: HashMap<String, Vec<[usize; 2]>>,
     },
     CommitOffsets {
         offsets: HashMap<String, usize>,
     },
     CommitOffsetsOk,
     ListCommittedOffsets {
-        keys: HashSet<String>,
+        keys: Vec<String>,
     },
     ListCommittedOffsetsOk {
         offsets: HashMap<String, usize>,
@@ -112,34 +192,139 @@ enum KafkaMessage {
 }
 
 fn handle_message(
-    input: &Message<KafkaMessage>,
-    _output: &Arc<Mutex<Sender<StdOutWriter>>>,
-    _state: &Arc<Mutex<InitState<KafkaState>>>,
-) {
-    match &input.body.msg_type {
-        KafkaMessage::Send { key, msg } => {
-            tracing::info!(key, msg, "Received Send");
-        }
-        KafkaMessage::SendOk { offset } => {
-            tracing::info!(offset, "Received SendOk");
-        }
-        KafkaMessage::Poll { offsets } => {
-            tracing::info!(?offsets, "Received Poll");
+    input: &Message<KafkaRequest>,
+    output: &Arc<Mutex<Sender<StdOutWriter>>>,
+    state: &Arc<Mutex<InitState<KafkaState>>>,
+) -> Result<()> {
+    match input.body.msg_type {
+        KafkaRequest::Send { ref key, msg } => {
+            let kv = LinKv::new(output.clone());
+            let key = key.clone();
+            let input = input.clone();
+            let output = output.clone();
+            let state = state.clone();
+            tokio::spawn(async move {
+                let reply = send(&kv, &key, msg).await.map(|offset| {
+                    let operation = KafkaOperation::Send { key, offset, msg };
+                    state.lock().unwrap().record(&operation);
+                    KafkaMessage::send_ok(offset)
+                });
+                let mut output = output.lock().unwrap();
+                let _ = match reply {
+                    Ok(reply) => output.reply_to(&input, reply),
+                    Err(error) => output.reply_error(&input, error.into()),
+                };
+            });
         }
-        KafkaMessage::PoolOk { msgs } => {
-            tracing::info!(?msgs, "Received PollOk");
-        }
-        KafkaMessage::CommitOffsets { offsets } => {
-            tracing::info!(?offsets, "Received CommitOffsets");
+        KafkaRequest::Poll { ref offsets } => {
+            let unsettled = state.lock().unwrap().unsettled(offsets);
+            if unsettled.is_empty() {
+                let msgs = state.lock().unwrap().poll(offsets);
+                let _ = output
+                    .lock()
+                    .unwrap()
+                    .reply_to(input, KafkaMessage::poll_ok(msgs));
+                return Ok(());
+            }
+
+            let kv = LinKv::new(output.clone());
+            let offsets = offsets.clone();
+            let input = input.clone();
+            let output = output.clone();
+            let state = state.clone();
+            tokio::spawn(async move {
+                let settled = async {
+                    for (key, offset) in unsettled {
+                        let operation = settle(&kv, key, offset).await?;
+                        state.lock().unwrap().record(&operation);
+                    }
+                    Ok::<_, KvError>(())
+                }
+                .await;
+                let reply =
+                    settled.map(|()| KafkaMessage::poll_ok(state.lock().unwrap().poll(&offsets)));
+                let mut output = output.lock().unwrap();
+                let _ = match reply {
+                    Ok(reply) => output.reply_to(&input, reply),
+                    Err(error) => output.reply_error(&input, error.into()),
+                };
+            });
         }
-        KafkaMessage::CommitOffsetsOk => {
-            tracing::info!("Received CommitOffsetsOk");
+        KafkaRequest::CommitOffsets { ref offsets } => {
+            let operation = KafkaOperation::Commit {
+                offsets: offsets.clone(),
+            };
+            state.lock().unwrap().record(&operation);
+
+            let _ = output
+                .lock()
+                .unwrap()
+                .reply_to(input, KafkaMessage::commit_offsets_ok());
         }
-        KafkaMessage::ListCommittedOffsets { keys } => {
-            tracing::info!(?keys, "Received ListCommittedOffsets");
+        KafkaRequest::ListCommittedOffsets { ref keys } => {
+            let offsets = state.lock().unwrap().committed_offsets(keys.iter());
+            let _ = output
+                .lock()
+                .unwrap()
+                .reply_to(input, KafkaMessage::list_committed_offsets_ok(offsets));
         }
-        KafkaMessage::ListCommittedOffsetsOk { offsets } => {
-            tracing::info!(?offsets, "Received ListCommittedOffsetsOk");
+    };
+    Ok(())
+}
+
+/// Stores `msg` at a new offset of `key`. An offset can be taken and never
+/// filled, when the `cas` taking it times out after being applied or its
+/// node dies: polls then skip it, see [`settle`], and a send that finds its
+/// offset skipped takes the next one.
+async fn send(kv: &LinKv<StdOutWriter>, key: &str, msg: usize) -> Result<usize, KvError> {
+    loop {
+        let offset = next_offset(kv, key).await?;
+        match kv.cas(slot(key, offset), Some(msg), Some(msg), true).await {
+            Ok(()) => return Ok(offset),
+            Err(KvError::PreconditionFailed) => continue,
+            Err(error) => return Err(error),
         }
+    }
+}
+
+/// Learns what `offset` of `key` holds from `lin-kv`, skipping it if nothing
+/// was stored there yet so that no send can fill it once polls moved past it.
+/// The outcome is recorded, which spreads it to the other nodes.
+async fn settle(
+    kv: &LinKv<StdOutWriter>,
+    key: String,
+    offset: usize,
+) -> Result<KafkaOperation, KvError> {
+    let slot = slot(&key, offset);
+    let stored = match kv.cas(&slot, None::<usize>, None::<usize>, true).await {
+        Ok(()) => None,
+        Err(KvError::PreconditionFailed) => kv.read(&slot).await?,
+        Err(error) => return Err(error),
     };
+    Ok(match stored {
+        Some(msg) => KafkaOperation::Send { key, offset, msg },
+        None => KafkaOperation::Skip { key, offset },
+    })
+}
+
+fn slot(key: &str, offset: usize) -> String {
+    format!("{SLOTS_KEY}/{key}/{offset}")
+}
+
+/// Takes the next offset of `key` from `lin-kv`, where every node agrees on
+/// it: offsets go up one by one, each handed out once
+async fn next_offset(kv: &LinKv<StdOutWriter>, key: &str) -> Result<usize, KvError> {
+    let counter = format!("{OFFSETS_KEY}/{key}");
+    loop {
+        let offset = match kv.read::<usize>(&counter).await {
+            Ok(offset) => offset,
+            Err(KvError::KeyDoesNotExist) => 0,
+            Err(error) => return Err(error),
+        };
+        match kv.cas(&counter, offset, offset + 1, true).await {
+            Ok(()) => return Ok(offset),
+            Err(KvError::PreconditionFailed) => continue,
+            Err(error) => return Err(error),
+        }
+    }
 }
@@ -0,0 +1,381 @@
+//! Runs a workload against local copies of a node binary, no Maelstrom needed.
+//!
+//! ```text
+//! maelstrom-lite <workload> <binary> [--node-count N] [--time-limit SECONDS]
+//!     [--rate OPS_PER_SECOND] [--concurrency CLIENTS] [--latency MILLISECONDS]
+//!     [--history FILE] [--consistency MODEL]
+//! ```
+//!
+//! Workloads are `echo`, `unique-ids`, `broadcast`, `g-counter`, `pn-counter`,
+//! `kafka` and `txn-rw-register`, which is checked against `--consistency`:
+//! `read-uncommitted` or `read-committed` (the default).
+//! Once done, the history is validated with [`checker`] when the workload
+//! has one.
+//! Nodes can use the `seq-kv`, `lin-kv` and `lww-kv` services as usual. What
+//! they write to stderr is logged at debug level.
+
+use anyhow::{anyhow, bail, Context, Result};
+use rand::Rng;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufWriter,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use symmetrical_octo_potato::{
+    checker::{self, Consistency},
+    history::{History, Outcome},
+    random,
+    sim::{run_process, Client, NetworkConfig, NetworkStats, Sim},
+    topology::{Graph, Grid, Topology},
+};
+use tokio::time::Instant;
+use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*};
+
+const USAGE: &str = "usage: maelstrom-lite <workload> <binary> [--node-count N] \
+[--time-limit SECONDS] [--rate OPS_PER_SECOND] [--concurrency CLIENTS] \
+[--latency MILLISECONDS] [--history FILE] [--consistency MODEL]";
+
+const KAFKA_KEYS: &[&str] = &["k1", "k2", "k3"];
+
+/// Registers the `txn-rw-register` transactions pick from
+const TXN_KEYS: u64 = 8;
+
+#[derive(Clone, Copy, Debug)]
+enum Workload {
+    Echo,
+    UniqueIds,
+    Broadcast,
+    GCounter,
+    PnCounter,
+    Kafka,
+    TxnRwRegister,
+}
+
+impl Workload {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "echo" => Ok(Self::Echo),
+            "unique-ids" => Ok(Self::UniqueIds),
+            "broadcast" => Ok(Self::Broadcast),
+            "g-counter" => Ok(Self::GCounter),
+            "pn-counter" => Ok(Self::PnCounter),
+            "kafka" => Ok(Self::Kafka),
+            "txn-rw-register" => Ok(Self::TxnRwRegister),
+            other => bail!("Unknown workload {other:?}\n{USAGE}"),
+        }
+    }
+}
+
+struct Options {
+    workload: Workload,
+    binary: PathBuf,
+    node_count: usize,
+    time_limit: Duration,
+    rate: f64,
+    concurrency: usize,
+    latency: Duration,
+    history: Option<PathBuf>,
+    consistency: Consistency,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let workload = Workload::parse(&args.next().context(USAGE)?)?;
+        let binary = PathBuf::from(args.next().context(USAGE)?);
+        let mut options = Self {
+            workload,
+            binary,
+            node_count: 3,
+            time_limit: Duration::from_secs(5),
+            rate: 10.0,
+            concurrency: 0,
+            latency: Duration::ZERO,
+            history: None,
+            consistency: Consistency::ReadCommitted,
+        };
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("Missing value for {flag}\n{USAGE}"))?;
+            let invalid = || format!("Invalid value {value:?} for {flag}");
+            match flag.as_str() {
+                "--node-count" => options.node_count = value.parse().with_context(invalid)?,
+                "--time-limit" => {
+                    options.time_limit =
+                        Duration::from_secs_f64(value.parse().with_context(invalid)?);
+                }
+                "--rate" => options.rate = value.parse().with_context(invalid)?,
+                "--concurrency" => options.concurrency = value.parse().with_context(invalid)?,
+                "--latency" => {
+                    options.latency = Duration::from_millis(value.parse().with_context(invalid)?);
+                }
+                "--history" => options.history = Some(PathBuf::from(value)),
+                "--consistency" => {
+                    options.consistency = match value.as_str() {
+                        "read-uncommitted" => Consistency::ReadUncommitted,
+                        "read-committed" => Consistency::ReadCommitted,
+                        _ => bail!("{}", invalid()),
+                    };
+                }
+                other => bail!("Unknown option {other:?}\n{USAGE}"),
+            }
+        }
+        if options.node_count == 0 || options.rate <= 0.0 {
+            bail!("--node-count and --rate must be positive");
+        }
+        if options.concurrency == 0 {
+            options.concurrency = 2 * options.node_count;
+        }
+        Ok(options)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let layer = fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(false)
+        .compact()
+        .with_filter(LevelFilter::INFO);
+    tracing_subscriber::registry().with(layer).init();
+
+    let options = Options::parse(std::env::args().skip(1))?;
+    let mut sim = Sim::new(NetworkConfig {
+        latency: (Duration::ZERO, options.latency),
+        ..NetworkConfig::default()
+    });
+    let binary = options.binary.clone();
+    sim.add_nodes(options.node_count, |writer, input| {
+        run_process(binary.clone(), writer, input)
+    });
+    sim.init().await?;
+
+    let nodes: Vec<String> = sim.node_ids().iter().cloned().collect();
+    if let Workload::Broadcast = options.workload {
+        let client = sim.client();
+        let topology = Grid.graph(sim.node_ids(), &Graph::new());
+        for node in &nodes {
+            client
+                .call::<_, Value>(node, json!({"type": "topology", "topology": topology}))
+                .await
+                .with_context(|| format!("Sending topology to {node}"))?;
+        }
+    }
+
+    let history = History::default();
+    let counter = Arc::new(AtomicUsize::new(0));
+    let deadline = Instant::now() + options.time_limit;
+    let period = Duration::from_secs_f64(options.concurrency as f64 / options.rate);
+    let mut clients = Vec::new();
+    for _ in 0..options.concurrency {
+        let client = sim.client();
+        let process = format!("c{}", clients.len() + 1);
+        let history = history.clone();
+        let counter = counter.clone();
+        let nodes = nodes.clone();
+        let workload = options.workload;
+        clients.push(tokio::spawn(async move {
+            let mut generator = Generator::new(workload, counter);
+            while Instant::now() < deadline {
+                let node = random::with_rng(|rng| nodes[rng.gen_range(0..nodes.len())].clone());
+                let request = generator.next();
+                let outcome = call(&history, &client, &process, &node, request).await;
+                generator.observe(&outcome);
+                tokio::time::sleep(period).await;
+            }
+        }));
+    }
+    for client in clients {
+        client.await?;
+    }
+
+    // Let the nodes catch up before the final reads, as Maelstrom does
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let client = sim.client();
+    for node in &nodes {
+        for request in final_reads(options.workload) {
+            call(&history, &client, "final", node, request).await;
+        }
+    }
+
+    summarize(&history, sim.stats());
+    if let Some(path) = &options.history {
+        let file = File::create(path).with_context(|| format!("Creating {}", path.display()))?;
+        history.write_to(BufWriter::new(file))?;
+    }
+
+    let operations = history.operations();
+    let verdict = match options.workload {
+        Workload::Echo | Workload::UniqueIds => return Ok(()),
+        Workload::Broadcast => checker::broadcast(&operations),
+        Workload::GCounter => checker::g_counter(&operations),
+        Workload::PnCounter => checker::pn_counter(&operations),
+        Workload::Kafka => checker::kafka(&operations),
+        Workload::TxnRwRegister => checker::txn_rw_register(&operations, options.consistency),
+    };
+    match verdict {
+        Ok(()) => {
+            println!("Everything looks good!");
+            Ok(())
+        }
+        Err(anomalies) => bail!("The history is not valid:\n{anomalies}"),
+    }
+}
+
+async fn call(
+    history: &History,
+    client: &Client,
+    process: &str,
+    node: &str,
+    request: Value,
+) -> Outcome {
+    let reply = client.call::<_, Value>(node, request.clone());
+    history.record(process, node, request, reply).await
+}
+
+/// Produces the requests of one client
+struct Generator {
+    workload: Workload,
+    // Shared between clients, so that broadcast and kafka values are unique
+    counter: Arc<AtomicUsize>,
+    // Kafka offsets this client has consumed up to
+    offsets: HashMap<String, usize>,
+}
+
+impl Generator {
+    fn new(workload: Workload, counter: Arc<AtomicUsize>) -> Self {
+        Self {
+            workload,
+            counter,
+            offsets: HashMap::new(),
+        }
+    }
+
+    fn next(&mut self) -> Value {
+        let unique = self.counter.fetch_add(1, Ordering::Relaxed);
+        let choice = random::with_rng(|rng| rng.gen_range(0..6));
+        match self.workload {
+            Workload::Echo => json!({"type": "echo", "echo": format!("Please echo {unique}")}),
+            Workload::UniqueIds => json!({"type": "generate"}),
+            Workload::Broadcast if choice < 3 => json!({"type": "broadcast", "message": unique}),
+            Workload::Broadcast => json!({"type": "read"}),
+            Workload::GCounter if choice < 3 => {
+                let delta = random::with_rng(|rng| rng.gen_range(0..5));
+                json!({"type": "add", "delta": delta})
+            }
+            Workload::GCounter => json!({"type": "read"}),
+            Workload::PnCounter if choice < 3 => {
+                let delta = random::with_rng(|rng| rng.gen_range(-5..5));
+                json!({"type": "add", "delta": delta})
+            }
+            Workload::PnCounter => json!({"type": "read"}),
+            Workload::Kafka => match choice {
+                0..=2 => {
+                    let key = random::with_rng(|rng| rng.gen_range(0..KAFKA_KEYS.len()));
+                    json!({"type": "send", "key": KAFKA_KEYS[key], "msg": unique})
+                }
+                3 => json!({"type": "poll", "offsets": self.poll_offsets()}),
+                4 => {
+                    json!({"type": "commit_offsets", "offsets": self.offsets})
+                }
+                _ => json!({"type": "list_committed_offsets", "keys": KAFKA_KEYS}),
+            },
+            Workload::TxnRwRegister => json!({"type": "txn", "txn": self.txn()}),
+        }
+    }
+
+    /// One to four micro-ops, writes each with a value never written before
+    fn txn(&self) -> Vec<Value> {
+        let len = random::with_rng(|rng| rng.gen_range(1..=4));
+        (0..len)
+            .map(|_| {
+                let key = random::with_rng(|rng| rng.gen_range(0..TXN_KEYS));
+                if random::with_rng(|rng| rng.gen_bool(0.5)) {
+                    json!(["r", key, null])
+                } else {
+                    json!(["w", key, self.counter.fetch_add(1, Ordering::Relaxed)])
+                }
+            })
+            .collect()
+    }
+
+    /// Learns from replies, so that kafka polls move forward
+    fn observe(&mut self, outcome: &Outcome) {
+        let Outcome::Ok { reply } = outcome else {
+            return;
+        };
+        let Some(msgs) = reply["msgs"].as_object() else {
+            return;
+        };
+        for (key, messages) in msgs {
+            let last = messages
+                .as_array()
+                .and_then(|messages| messages.last())
+                .and_then(|message| message[0].as_u64());
+            if let Some(last) = last.and_then(|last| usize::try_from(last).ok()) {
+                self.offsets.insert(key.clone(), last);
+            }
+        }
+    }
+
+    fn poll_offsets(&self) -> HashMap<&str, usize> {
+        KAFKA_KEYS
+            .iter()
+            .map(|key| (*key, self.offsets.get(*key).map_or(0, |offset| offset + 1)))
+            .collect()
+    }
+}
+
+/// Requests sent to every node once the workload is over
+fn final_reads(workload: Workload) -> Vec<Value> {
+    match workload {
+        Workload::Echo | Workload::UniqueIds => Vec::new(),
+        Workload::Broadcast | Workload::GCounter | Workload::PnCounter => {
+            vec![json!({"type": "read"})]
+        }
+        Workload::TxnRwRegister => {
+            let txn: Vec<_> = (0..TXN_KEYS).map(|key| json!(["r", key, null])).collect();
+            vec![json!({"type": "txn", "txn": txn})]
+        }
+        Workload::Kafka => {
+            let offsets: HashMap<_, _> = KAFKA_KEYS.iter().map(|key| (*key, 0)).collect();
+            vec![
+                json!({"type": "poll", "offsets": offsets}),
+                json!({"type": "list_committed_offsets", "keys": KAFKA_KEYS}),
+            ]
+        }
+    }
+}
+
+/// Prints how many requests of each type succeeded, failed or may have, and
+/// how many messages nodes sent each other per request
+fn summarize(history: &History, stats: &NetworkStats) {
+    let mut counts: BTreeMap<String, [usize; 3]> = BTreeMap::new();
+    for operation in history.operations() {
+        let count = counts
+            .entry(operation.request_type().to_string())
+            .or_default();
+        match operation.outcome {
+            Outcome::Ok { .. } => count[0] += 1,
+            Outcome::Fail { .. } => count[1] += 1,
+            Outcome::Info { .. } => count[2] += 1,
+        }
+    }
+    println!("{:<24} {:>8} {:>8} {:>8}", "request", "ok", "fail", "info");
+    for (request, [ok, fail, info]) in counts {
+        println!("{request:<24} {ok:>8} {fail:>8} {info:>8}");
+    }
+    let requests = history.operations().len().max(1);
+    let between_nodes = stats.between_nodes.load(Ordering::Relaxed);
+    println!(
+        "messages between nodes per request: {:.2}",
+        between_nodes as f64 / requests as f64
+    );
+}
@@ -0,0 +1,86 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use symmetrical_octo_potato::{
+    crdt::{Crdt, PnCounter},
+    init_state::{Init, InitState, Initable},
+    message::Message,
+    protocol::MaelstromProtocol,
+    sender::Sender,
+    stdout_writer::StdOutWriter,
+    traits::replica::Replica,
+    Node,
+};
+
+struct PnCounterState {
+    counter: PnCounter,
+}
+
+impl Initable for PnCounterState {
+    fn with_init(_: Init) -> Self {
+        Self {
+            counter: PnCounter::default(),
+        }
+    }
+}
+
+impl Replica for PnCounterState {
+    type Crdt = PnCounter;
+
+    fn crdt(&self) -> &PnCounter {
+        &self.counter
+    }
+
+    fn crdt_mut(&mut self) -> &mut PnCounter {
+        &mut self.counter
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    Node::<PnCounterState>::new()
+        .with_gossip(Duration::from_millis(100))
+        .run(handle_message)
+        .await
+}
+
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum PnCounterMessage {
+    Add { delta: i64 },
+    Read,
+    AddOk,
+    ReadOk { value: i64 },
+}
+
+fn handle_message(
+    input: &Message<PnCounterRequest>,
+    output: &Arc<Mutex<Sender<StdOutWriter>>>,
+    state: &Arc<Mutex<InitState<PnCounterState>>>,
+) -> Result<()> {
+    match input.body.msg_type {
+        PnCounterRequest::Add { delta } => {
+            let mut state = state.lock().unwrap();
+            let node = state.get_init().node_id.clone();
+            state.counter.add(&node, delta);
+            std::mem::drop(state);
+
+            let _ = output
+                .lock()
+                .unwrap()
+                .reply_to(input, PnCounterMessage::add_ok());
+        }
+        PnCounterRequest::Read => {
+            let value = state.lock().unwrap().counter.value();
+            let _ = output
+                .lock()
+                .unwrap()
+                .reply_to(input, PnCounterMessage::read_ok(value));
+        }
+    };
+    Ok(())
+}
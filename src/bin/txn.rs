@@ -0,0 +1,170 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use symmetrical_octo_potato::{
+    crdt::{Crdt, LwwRegister, Map},
+    init_state::{Init, InitState, Initable},
+    message::{ErrorCode, MaelstromError, Message},
+    protocol::MaelstromProtocol,
+    sender::Sender,
+    stdout_writer::StdOutWriter,
+    traits::replica::Replica,
+    Node,
+};
+
+/// Picks what transactions guarantee: `single-node` keeps the registers to
+/// the node, `read-uncommitted` and `read-committed` (the default) replicate
+/// them to every node with gossip. Either way transactions never wait for
+/// other nodes.
+const MODE: &str = "TXN_MODE";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    SingleNode,
+    ReadUncommitted,
+    ReadCommitted,
+}
+
+/// `["r", key, value read]` or `["w", key, value written]`
+type MicroOp = (String, u64, Option<i64>);
+
+type Registers = Map<u64, LwwRegister<i64>>;
+
+struct TxnState {
+    registers: Registers,
+    /// Lamport clock, never below the timestamp of a write merged so far
+    clock: usize,
+}
+
+impl TxnState {
+    /// Runs `txn`, stamping all its writes with one timestamp above every
+    /// write it could have read: transactions are ordered the same way
+    /// everywhere, so their writes and reads never depend on each other in
+    /// a cycle. Every micro-op is checked before any runs, so a transaction
+    /// that fails leaves nothing behind. Read-committed only applies the
+    /// last write of each key once every micro-op ran, read-uncommitted
+    /// applies writes as they come.
+    fn run(&mut self, node: &str, mode: Mode, txn: &[MicroOp]) -> Result<Vec<MicroOp>> {
+        let writes = txn
+            .iter()
+            .map(|(f, key, value)| match (f.as_str(), value) {
+                ("r", _) => Ok(None),
+                ("w", Some(value)) => Ok(Some(*value)),
+                ("w", None) => Err(MaelstromError::new(
+                    ErrorCode::MalformedRequest,
+                    format!("no value to write to {key}"),
+                )),
+                (other, _) => Err(MaelstromError::new(
+                    ErrorCode::NotSupported,
+                    format!("unknown micro-op {other:?}"),
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.clock += 1;
+        let timestamp = self.clock;
+        let mut pending = BTreeMap::new();
+        let mut done = Vec::with_capacity(txn.len());
+        for ((f, key, _), write) in txn.iter().zip(writes) {
+            let value = match write {
+                None => pending
+                    .get(key)
+                    .copied()
+                    .or_else(|| self.registers.get(key).and_then(Crdt::value)),
+                Some(value) if mode == Mode::ReadUncommitted => {
+                    self.registers.entry(*key).set_at(timestamp, node, value);
+                    Some(value)
+                }
+                Some(value) => {
+                    pending.insert(*key, value);
+                    Some(value)
+                }
+            };
+            done.push((f.clone(), *key, value));
+        }
+        for (key, value) in pending {
+            self.registers.entry(key).set_at(timestamp, node, value);
+        }
+        Ok(done)
+    }
+}
+
+impl Initable for TxnState {
+    fn with_init(_: Init) -> Self {
+        Self {
+            registers: Map::default(),
+            clock: 0,
+        }
+    }
+}
+
+impl Replica for TxnState {
+    type Crdt = Registers;
+
+    fn crdt(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn crdt_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    fn merge(&mut self, delta: &Registers) {
+        self.registers.merge(delta);
+        self.clock = delta
+            .iter()
+            .filter_map(|(_, register)| register.timestamp())
+            .fold(self.clock, usize::max);
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mode = match std::env::var(MODE).as_deref() {
+        Ok("single-node") => Mode::SingleNode,
+        Ok("read-uncommitted") => Mode::ReadUncommitted,
+        Ok("read-committed") | Err(_) => Mode::ReadCommitted,
+        Ok(other) => bail!("Unknown mode {other:?}"),
+    };
+    let node = Node::<TxnState>::new();
+    let node = match mode {
+        Mode::SingleNode => node,
+        Mode::ReadUncommitted | Mode::ReadCommitted => node.with_gossip(Duration::from_millis(100)),
+    };
+    node.run(move |input, output, state| handle_message(input, output, state, mode))
+        .await
+}
+
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum TxnMessage {
+    Txn { txn: Vec<MicroOp> },
+    TxnOk { txn: Vec<MicroOp> },
+}
+
+fn handle_message(
+    input: &Message<TxnRequest>,
+    output: &Arc<Mutex<Sender<StdOutWriter>>>,
+    state: &Arc<Mutex<InitState<TxnState>>>,
+    mode: Mode,
+) -> Result<()> {
+    match input.body.msg_type {
+        TxnRequest::Txn { ref txn } => {
+            let mut state = state.lock().unwrap();
+            let node = state.get_init().node_id.clone();
+            let txn = state.run(&node, mode, txn)?;
+            std::mem::drop(state);
+
+            let _ = output
+                .lock()
+                .unwrap()
+                .reply_to(input, TxnMessage::txn_ok(txn));
+        }
+    };
+    Ok(())
+}
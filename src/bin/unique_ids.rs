@@ -1,14 +1,14 @@
-use anyhow::{bail, Result};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use symmetrical_octo_potato::{
-    init_state::{init_parser, Init, Initable},
+    init_state::{Init, InitState, Initable},
     message::Message,
+    protocol::MaelstromProtocol,
     sender::Sender,
     stdout_writer::StdOutWriter,
-    wait_for_message_then,
+    Node,
 };
-use tracing_subscriber::{fmt, prelude::*};
 use ulid::Ulid;
 
 struct UniqueIdsState {}
@@ -21,21 +21,10 @@ impl Initable for UniqueIdsState {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let layer = fmt::layer()
-        .with_writer(std::io::stderr)
-        .with_thread_ids(true)
-        .with_ansi(false)
-        .pretty();
-    tracing_subscriber::registry().with(layer).init();
-    let (tx, mut rx) = tokio::sync::broadcast::channel(16);
-    let output = Arc::new(Mutex::new(Sender::default()));
-    symmetrical_octo_potato::init_stdin(tx.clone());
-    let _ = init_parser::<UniqueIdsState, StdOutWriter>(tx.subscribe(), output.clone()).await?;
-    let _ = wait_for_message_then(&mut rx, |msg| handle_message(msg, output.clone())).await;
-    Ok(())
+    Node::<UniqueIdsState>::new().run(handle_message).await
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum UniqueIdMessage {
@@ -47,20 +36,16 @@ enum UniqueIdMessage {
 }
 
 fn handle_message(
-    input: Message<UniqueIdMessage>,
-    output: Arc<Mutex<Sender<StdOutWriter>>>,
+    input: &Message<UniqueIdRequest>,
+    output: &Arc<Mutex<Sender<StdOutWriter>>>,
+    _state: &Arc<Mutex<InitState<UniqueIdsState>>>,
 ) -> Result<()> {
     match input.body.msg_type {
-        UniqueIdMessage::Generate { .. } => {
-            let _ = output.lock().unwrap().reply(
-                input,
-                UniqueIdMessage::GenerateOk {
-                    guid: Ulid::new().to_string(),
-                },
-            );
-        }
-        UniqueIdMessage::GenerateOk { .. } => {
-            bail!("not expecting this");
+        UniqueIdRequest::Generate => {
+            let _ = output
+                .lock()
+                .unwrap()
+                .reply_to(input, UniqueIdMessage::generate_ok(Ulid::new().to_string()));
         }
     };
     Ok(())
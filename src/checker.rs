@@ -0,0 +1,833 @@
+//! Checks a recorded [`History`](crate::history::History) against the
+//! guarantees of each workload.
+//!
+//! Final reads are the last successful read each node answered. Operations
+//! that may or may not have taken place (`info`) are allowed either way.
+
+use crate::{
+    history::{Operation, Outcome},
+    kv::KvMessage,
+    message::ErrorCode,
+};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+
+/// Everything a checker found wrong with a history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Anomalies(pub Vec<String>);
+
+impl Display for Anomalies {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.join("\n"))
+    }
+}
+
+impl std::error::Error for Anomalies {}
+
+fn verdict(anomalies: Vec<String>) -> Result<(), Anomalies> {
+    if anomalies.is_empty() {
+        Ok(())
+    } else {
+        Err(Anomalies(anomalies))
+    }
+}
+
+fn requests<'a>(
+    history: &'a [Operation],
+    request_type: &'a str,
+) -> impl Iterator<Item = &'a Operation> {
+    history
+        .iter()
+        .filter(move |operation| operation.request_type() == request_type)
+}
+
+/// The last successful `request_type` each node answered
+fn last_answered<'a>(
+    history: &'a [Operation],
+    request_type: &'a str,
+) -> BTreeMap<&'a str, &'a Operation> {
+    let mut last: BTreeMap<&str, &Operation> = BTreeMap::new();
+    for operation in requests(history, request_type).filter(|op| op.reply().is_some()) {
+        let latest = last.entry(&operation.node).or_insert(operation);
+        if operation.completed > latest.completed {
+            *latest = operation;
+        }
+    }
+    last
+}
+
+/// The replies of [`last_answered`], nodes that never answered are reported
+fn final_reads<'a>(
+    history: &'a [Operation],
+    request_type: &'a str,
+    anomalies: &mut Vec<String>,
+) -> BTreeMap<&'a str, &'a Value> {
+    let last = last_answered(history, request_type);
+    let nodes: BTreeSet<&str> = history.iter().map(|op| op.node.as_str()).collect();
+    for node in nodes.iter().filter(|node| !last.contains_key(*node)) {
+        anomalies.push(format!("{node} never answered a {request_type}"));
+    }
+    last.into_iter()
+        .filter_map(|(node, operation)| Some((node, operation.reply()?)))
+        .collect()
+}
+
+/// Every acknowledged `broadcast` is in the final read of every node, and
+/// reads only return messages that were broadcast
+///
+/// # Errors
+///
+/// - the anomalies found
+pub fn broadcast(history: &[Operation]) -> Result<(), Anomalies> {
+    let mut anomalies = Vec::new();
+    let mut attempted = BTreeSet::new();
+    let mut acknowledged = BTreeSet::new();
+    for operation in requests(history, "broadcast") {
+        let Some(message) = operation.request["message"].as_u64() else {
+            continue;
+        };
+        attempted.insert(message);
+        if operation.reply().is_some() {
+            acknowledged.insert(message);
+        }
+    }
+
+    let read = |reply: &Value| -> BTreeSet<u64> {
+        reply["messages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_u64)
+            .collect()
+    };
+    for operation in requests(history, "read") {
+        let Some(reply) = operation.reply() else {
+            continue;
+        };
+        let unexpected: Vec<_> = read(reply).difference(&attempted).copied().collect();
+        if !unexpected.is_empty() {
+            anomalies.push(format!(
+                "{} read {unexpected:?}, which were never broadcast",
+                operation.node
+            ));
+        }
+    }
+    for (node, reply) in final_reads(history, "read", &mut anomalies) {
+        let missing: Vec<_> = acknowledged.difference(&read(reply)).copied().collect();
+        if !missing.is_empty() {
+            anomalies.push(format!("{node} lost acknowledged messages {missing:?}"));
+        }
+    }
+    verdict(anomalies)
+}
+
+/// The final read of every node is the sum of the acknowledged `add`s, plus
+/// any of the ones that may have happened. No read goes over the sum of
+/// every `add` attempted.
+///
+/// # Errors
+///
+/// - the anomalies found
+pub fn g_counter(history: &[Operation]) -> Result<(), Anomalies> {
+    let mut anomalies = Vec::new();
+    let (mut acknowledged, mut uncertain) = (0, 0);
+    for operation in requests(history, "add") {
+        let delta = operation.request["delta"].as_u64().unwrap_or_default();
+        match operation.outcome {
+            Outcome::Ok { .. } => acknowledged += delta,
+            Outcome::Info { .. } => uncertain += delta,
+            Outcome::Fail { .. } => {}
+        }
+    }
+
+    let value = |reply: &Value| reply["value"].as_u64().unwrap_or_default();
+    for operation in requests(history, "read") {
+        if let Some(read) = operation.reply().map(value) {
+            if read > acknowledged + uncertain {
+                anomalies.push(format!(
+                    "{} read {read}, more than was ever added ({})",
+                    operation.node,
+                    acknowledged + uncertain
+                ));
+            }
+        }
+    }
+    for (node, reply) in final_reads(history, "read", &mut anomalies) {
+        let read = value(reply);
+        if read < acknowledged {
+            anomalies.push(format!(
+                "{node} finally read {read}, acknowledged adds sum up to {acknowledged}"
+            ));
+        }
+    }
+    verdict(anomalies)
+}
+
+/// Every read is somewhere between the sum of the `add`s that may have
+/// happened with a negative delta and of those with a positive one. Final
+/// reads are the sum of the acknowledged `add`s, plus any of the ones that
+/// may have happened.
+///
+/// # Errors
+///
+/// - the anomalies found
+pub fn pn_counter(history: &[Operation]) -> Result<(), Anomalies> {
+    let mut anomalies = Vec::new();
+    let mut acknowledged = 0;
+    // What the uncertain adds, then every add not failed, sum up to at least
+    // and at most
+    let (mut uncertain, mut possible) = ((0, 0), (0, 0));
+    for operation in requests(history, "add") {
+        let delta = operation.request["delta"].as_i64().unwrap_or_default();
+        let (low, high) = (delta.min(0), delta.max(0));
+        match operation.outcome {
+            Outcome::Ok { .. } => acknowledged += delta,
+            Outcome::Info { .. } => uncertain = (uncertain.0 + low, uncertain.1 + high),
+            Outcome::Fail { .. } => continue,
+        }
+        possible = (possible.0 + low, possible.1 + high);
+    }
+    let (floor, ceiling) = possible;
+    let (lowest, highest) = (acknowledged + uncertain.0, acknowledged + uncertain.1);
+
+    let value = |reply: &Value| reply["value"].as_i64().unwrap_or_default();
+    for operation in requests(history, "read") {
+        if let Some(read) = operation.reply().map(value) {
+            if !(floor..=ceiling).contains(&read) {
+                anomalies.push(format!(
+                    "{} read {read}, adds can only sum up to {floor}..={ceiling}",
+                    operation.node
+                ));
+            }
+        }
+    }
+    for (node, reply) in final_reads(history, "read", &mut anomalies) {
+        let read = value(reply);
+        if !(lowest..=highest).contains(&read) {
+            anomalies.push(format!(
+                "{node} finally read {read}, acknowledged adds sum up to {acknowledged} \
+                 and uncertain ones bring it to {lowest}..={highest}"
+            ));
+        }
+    }
+    verdict(anomalies)
+}
+
+/// Kafka logs: each offset of a key holds one message, and each message one
+/// offset. Offsets may leave gaps, as in Maelstrom, but polls return offsets in
+/// order and never jump over an offset known to hold a message. Polls skip
+/// no message acknowledged before they started, and the final polls miss none.
+///
+/// # Errors
+///
+/// - the anomalies found
+pub fn kafka(history: &[Operation]) -> Result<(), Anomalies> {
+    let mut anomalies = Vec::new();
+    // key -> offset -> messages found there
+    let mut logs: BTreeMap<String, BTreeMap<u64, BTreeSet<u64>>> = BTreeMap::new();
+    // key -> (offset, when it was acknowledged)
+    let mut sent: BTreeMap<String, Vec<(u64, Duration)>> = BTreeMap::new();
+
+    for operation in requests(history, "send") {
+        let (Some(key), Some(msg)) = (
+            operation.request["key"].as_str(),
+            operation.request["msg"].as_u64(),
+        ) else {
+            continue;
+        };
+        let Some(offset) = operation.reply().and_then(|reply| reply["offset"].as_u64()) else {
+            continue;
+        };
+        logs.entry(key.to_string())
+            .or_default()
+            .entry(offset)
+            .or_default()
+            .insert(msg);
+        sent.entry(key.to_string())
+            .or_default()
+            .push((offset, operation.completed));
+    }
+
+    let polls = |operation: &Operation| -> BTreeMap<String, Vec<(u64, u64)>> {
+        let Some(msgs) = operation
+            .reply()
+            .and_then(|reply| reply["msgs"].as_object())
+        else {
+            return BTreeMap::new();
+        };
+        msgs.iter()
+            .map(|(key, messages)| {
+                let messages = messages
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|pair| Some((pair[0].as_u64()?, pair[1].as_u64()?)))
+                    .collect();
+                (key.clone(), messages)
+            })
+            .collect()
+    };
+    let final_polls = last_answered(history, "poll");
+    let mut polled_offsets = Vec::new();
+
+    for operation in requests(history, "poll") {
+        let is_final = final_polls
+            .values()
+            .any(|last| std::ptr::eq(*last, operation));
+        for (key, messages) in polls(operation) {
+            for &(offset, msg) in &messages {
+                logs.entry(key.clone())
+                    .or_default()
+                    .entry(offset)
+                    .or_default()
+                    .insert(msg);
+            }
+            if messages.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+                anomalies.push(format!(
+                    "{} polled non-monotonic offsets for {key}: {messages:?}",
+                    operation.node
+                ));
+            }
+
+            let from = operation.request["offsets"][&key]
+                .as_u64()
+                .unwrap_or_default();
+            let until = if is_final {
+                u64::MAX
+            } else {
+                messages.last().map_or(from, |(offset, _)| *offset)
+            };
+            let polled: BTreeSet<u64> = messages.iter().map(|(offset, _)| *offset).collect();
+            let skipped: Vec<u64> = sent
+                .get(&key)
+                .into_iter()
+                .flatten()
+                .filter(|(offset, completed)| {
+                    (from..until).contains(offset)
+                        && *completed < operation.invoked
+                        && !polled.contains(offset)
+                })
+                .map(|(offset, _)| *offset)
+                .collect();
+            if !skipped.is_empty() {
+                anomalies.push(format!(
+                    "{} skipped acknowledged offsets {skipped:?} of {key}",
+                    operation.node
+                ));
+            }
+            polled_offsets.push((&operation.node, key, polled));
+        }
+    }
+
+    // Only known once every send and poll was seen
+    for (node, key, polled) in polled_offsets {
+        let (Some(first), Some(last)) = (polled.first(), polled.last()) else {
+            continue;
+        };
+        let holes: Vec<u64> = logs[&key]
+            .range(first..last)
+            .map(|(offset, _)| *offset)
+            .filter(|offset| !polled.contains(offset))
+            .collect();
+        if !holes.is_empty() {
+            anomalies.push(format!(
+                "{node} polled past offsets {holes:?} of {key}, which hold messages"
+            ));
+        }
+    }
+
+    for (key, log) in &logs {
+        let mut offsets: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for (offset, messages) in log {
+            if messages.len() > 1 {
+                anomalies.push(format!(
+                    "offset {offset} of {key} holds several messages {messages:?}"
+                ));
+            }
+            for msg in messages {
+                offsets.entry(*msg).or_default().push(*offset);
+            }
+        }
+        for (msg, offsets) in offsets.iter().filter(|(_, offsets)| offsets.len() > 1) {
+            anomalies.push(format!(
+                "message {msg} of {key} is at several offsets {offsets:?}"
+            ));
+        }
+    }
+    verdict(anomalies)
+}
+
+/// What a `lin-kv` operation observed or did to its key
+#[derive(Debug)]
+enum KvEffect {
+    /// `None` when the key did not exist
+    Read(Option<Value>),
+    Write(Value),
+    Cas {
+        from: Value,
+        to: Value,
+        /// A missing key is created with `to`
+        create_if_not_exists: bool,
+    },
+    /// The key held something other than `from`
+    CasFailed {
+        from: Value,
+    },
+}
+
+impl KvEffect {
+    /// The state of the key after this operation, if it could happen on
+    /// `state`
+    fn apply(&self, state: &Option<Value>) -> Option<Option<Value>> {
+        match self {
+            Self::Read(value) => (state == value).then(|| state.clone()),
+            Self::Write(value) => Some(Some(value.clone())),
+            Self::Cas {
+                from,
+                to,
+                create_if_not_exists,
+            } => (state.as_ref() == Some(from) || state.is_none() && *create_if_not_exists)
+                .then(|| Some(to.clone())),
+            Self::CasFailed { from } => {
+                matches!(state, Some(current) if current != from).then(|| state.clone())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct KvCall {
+    invoked: Duration,
+    /// `None` if the operation may not have happened at all
+    completed: Option<Duration>,
+    effect: KvEffect,
+}
+
+impl KvCall {
+    /// `None` when the operation tells nothing about the key
+    fn new(operation: &Operation) -> Option<(String, Self)> {
+        let request: KvMessage = serde_json::from_value(operation.request.clone()).ok()?;
+        let (key, effect) = match (request, &operation.outcome) {
+            (KvMessage::Read { key }, Outcome::Ok { reply }) => {
+                (key, KvEffect::Read(Some(reply["value"].clone())))
+            }
+            (KvMessage::Read { key }, Outcome::Fail { error })
+                if error.code == ErrorCode::KeyDoesNotExist =>
+            {
+                (key, KvEffect::Read(None))
+            }
+            (KvMessage::Write { key, value }, Outcome::Ok { .. } | Outcome::Info { .. }) => {
+                (key, KvEffect::Write(value))
+            }
+            (
+                KvMessage::Cas {
+                    key,
+                    from,
+                    to,
+                    create_if_not_exists,
+                },
+                Outcome::Ok { .. } | Outcome::Info { .. },
+            ) => (
+                key,
+                KvEffect::Cas {
+                    from,
+                    to,
+                    create_if_not_exists,
+                },
+            ),
+            (KvMessage::Cas { key, .. }, Outcome::Fail { error })
+                if error.code == ErrorCode::KeyDoesNotExist =>
+            {
+                (key, KvEffect::Read(None))
+            }
+            (KvMessage::Cas { key, from, .. }, Outcome::Fail { error })
+                if error.code == ErrorCode::PreconditionFailed =>
+            {
+                (key, KvEffect::CasFailed { from })
+            }
+            _ => return None,
+        };
+        let completed = match operation.outcome {
+            Outcome::Info { .. } => None,
+            Outcome::Ok { .. } | Outcome::Fail { .. } => Some(operation.completed),
+        };
+        Some((
+            key.to_string(),
+            Self {
+                invoked: operation.invoked,
+                completed,
+                effect,
+            },
+        ))
+    }
+}
+
+/// Every `lin-kv` key behaves as a single register: there is an order of
+/// the operations, consistent with real time, in which every read sees the
+/// latest write. Keys are checked independently, each with a depth-first
+/// search over the operations that can take effect next, remembering the
+/// dead ends (as Knossos and Porcupine do).
+///
+/// # Errors
+///
+/// - the anomalies found
+pub fn lin_kv(history: &[Operation]) -> Result<(), Anomalies> {
+    let mut keys: BTreeMap<String, Vec<KvCall>> = BTreeMap::new();
+    for (key, call) in history.iter().filter_map(KvCall::new) {
+        keys.entry(key).or_default().push(call);
+    }
+    verdict(
+        keys.into_iter()
+            .filter(|(_, calls)| !linearizable(calls))
+            .map(|(key, calls)| format!("operations on key {key} are not linearizable: {calls:?}"))
+            .collect(),
+    )
+}
+
+fn linearizable(calls: &[KvCall]) -> bool {
+    /// A state the search reached, with the call that led to it
+    struct Frame {
+        state: Option<Value>,
+        call: Option<usize>,
+        /// Whatever goes next must have started before every pending
+        /// operation completed
+        horizon: Duration,
+        /// The next call to try from here
+        next: usize,
+    }
+
+    let mut done = vec![false; calls.len()];
+    let mut dead_ends: HashSet<(Vec<bool>, String)> = HashSet::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut reached = Some((None, None));
+    loop {
+        if let Some((state, call)) = reached.take() {
+            let pending = || {
+                calls
+                    .iter()
+                    .zip(done.iter())
+                    .filter(|(_, done)| !**done)
+                    .map(|(call, _)| call)
+            };
+            // Operations that may not have happened can be left out
+            if pending().all(|call| call.completed.is_none()) {
+                return true;
+            }
+            if dead_ends.insert((done.clone(), format!("{state:?}"))) {
+                let horizon = pending()
+                    .filter_map(|call| call.completed)
+                    .min()
+                    .unwrap_or(Duration::MAX);
+                stack.push(Frame {
+                    state,
+                    call,
+                    horizon,
+                    next: 0,
+                });
+            } else if let Some(call) = call {
+                done[call] = false;
+            }
+            continue;
+        }
+
+        let Some(frame) = stack.last_mut() else {
+            return false;
+        };
+        while frame.next < calls.len() && reached.is_none() {
+            let i = frame.next;
+            frame.next += 1;
+            if done[i] || calls[i].invoked > frame.horizon {
+                continue;
+            }
+            if let Some(next) = calls[i].effect.apply(&frame.state) {
+                done[i] = true;
+                reached = Some((next, Some(i)));
+            }
+        }
+        if reached.is_none() {
+            if let Some(call) = stack.pop().and_then(|frame| frame.call) {
+                done[call] = false;
+            }
+        }
+    }
+}
+
+/// Isolation levels [`txn_rw_register`] checks histories against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consistency {
+    /// Only G0 (write cycles) is prohibited
+    ReadUncommitted,
+    /// G0, G1a (aborted reads), G1b (intermediate reads) and G1c (cycles of
+    /// writes and reads) are prohibited
+    ReadCommitted,
+}
+
+/// A `txn` micro-op: what it does, its key and the value written or read.
+/// Keys and values are kept in their serialized form.
+type MicroOp = (String, String, String);
+
+fn micro_ops(txn: &Value) -> Vec<MicroOp> {
+    txn.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|op| {
+            Some((
+                op[0].as_str()?.to_string(),
+                op[1].to_string(),
+                op[2].to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// Transactions over read/write registers, as in Maelstrom's
+/// `txn-rw-register` workload, show none of the anomalies `consistency`
+/// prohibits. Written values must be unique per key, so that each read
+/// tells which transaction it saw. Transactions depend on each other
+/// when one reads what the other wrote (wr), or overwrites a value it read
+/// (ww); cycles among them are found with Tarjan's algorithm. Blind writes
+/// are ordered by final reads: once every write of a key completed, the
+/// value each node read last was written after all the others.
+///
+/// # Errors
+///
+/// - the anomalies found
+pub fn txn_rw_register(history: &[Operation], consistency: Consistency) -> Result<(), Anomalies> {
+    let mut anomalies = Vec::new();
+    let txns: Vec<(&Operation, Vec<MicroOp>)> = requests(history, "txn")
+        .map(|operation| {
+            let txn = operation.reply().unwrap_or(&operation.request);
+            (operation, micro_ops(&txn["txn"]))
+        })
+        .collect();
+
+    // (key, value) -> who wrote it, and whether it was its last write of key
+    let mut writes: HashMap<(&str, &str), (usize, bool)> = HashMap::new();
+    for (t, (_, ops)) in txns.iter().enumerate() {
+        for (i, (f, key, value)) in ops.iter().enumerate() {
+            if f == "w" {
+                let last = !ops[i + 1..]
+                    .iter()
+                    .any(|(f, other, _)| f == "w" && other == key);
+                writes.insert((key, value), (t, last));
+            }
+        }
+    }
+
+    let describe = |t: usize| format!("{} {}", txns[t].0.node, txns[t].0.request["txn"]);
+    let (mut ww, mut wr) = (BTreeSet::new(), BTreeSet::new());
+    let committed = txns
+        .iter()
+        .enumerate()
+        .filter(|(_, (operation, _))| operation.reply().is_some());
+    for (t, (_, ops)) in committed {
+        let mut own: HashMap<&str, &str> = HashMap::new();
+        for (i, (f, key, value)) in ops.iter().enumerate() {
+            if f == "w" {
+                own.insert(key, value);
+                continue;
+            }
+            if let Some(written) = own.get(key.as_str()) {
+                if written != value {
+                    anomalies.push(format!(
+                        "{} read {key} = {value} after writing {written}",
+                        describe(t)
+                    ));
+                }
+                continue;
+            }
+            if value == "null" {
+                continue;
+            }
+            let Some(&(writer, last)) = writes.get(&(key.as_str(), value.as_str())) else {
+                anomalies.push(format!(
+                    "{} read {key} = {value}, which was never written",
+                    describe(t)
+                ));
+                continue;
+            };
+            if consistency == Consistency::ReadCommitted {
+                if matches!(txns[writer].0.outcome, Outcome::Fail { .. }) {
+                    anomalies.push(format!(
+                        "G1a: {} read {key} = {value} from the failed {}",
+                        describe(t),
+                        describe(writer)
+                    ));
+                } else if !last {
+                    anomalies.push(format!(
+                        "G1b: {} read {key} = {value}, an intermediate write of {}",
+                        describe(t),
+                        describe(writer)
+                    ));
+                }
+            }
+            wr.insert((writer, t));
+            if ops[i + 1..]
+                .iter()
+                .any(|(f, other, _)| f == "w" && other == key)
+            {
+                ww.insert((writer, t));
+            }
+        }
+    }
+
+    for (winner, committed) in last_writes(&txns, &writes, history, &mut anomalies).into_values() {
+        for writer in committed.into_iter().filter(|writer| *writer != winner) {
+            ww.insert((writer, winner));
+        }
+    }
+
+    let write_cycles = cycles(txns.len(), &ww);
+    for cycle in &write_cycles {
+        let cycle: Vec<_> = cycle.iter().map(|t| describe(*t)).collect();
+        anomalies.push(format!("G0: write cycle between {cycle:?}"));
+    }
+    if consistency == Consistency::ReadCommitted {
+        let all: BTreeSet<_> = ww.union(&wr).copied().collect();
+        for cycle in cycles(txns.len(), &all) {
+            if write_cycles.contains(&cycle) {
+                continue;
+            }
+            let cycle: Vec<_> = cycle.iter().map(|t| describe(*t)).collect();
+            anomalies.push(format!("G1c: cycle of writes and reads between {cycle:?}"));
+        }
+    }
+    verdict(anomalies)
+}
+
+/// For each key final reads agree on, the transaction that wrote it last and
+/// every committed transaction that wrote it
+fn last_writes<'a>(
+    txns: &'a [(&Operation, Vec<MicroOp>)],
+    writes: &HashMap<(&str, &str), (usize, bool)>,
+    history: &[Operation],
+    anomalies: &mut Vec<String>,
+) -> BTreeMap<&'a str, (usize, BTreeSet<usize>)> {
+    let mut writers: BTreeMap<&str, BTreeSet<usize>> = BTreeMap::new();
+    for (t, (_, ops)) in txns.iter().enumerate() {
+        for (_, key, _) in ops.iter().filter(|(f, ..)| f == "w") {
+            writers.entry(key).or_default().insert(t);
+        }
+    }
+
+    // key -> node -> value it read last
+    let mut finals: BTreeMap<&str, BTreeMap<&str, &str>> = BTreeMap::new();
+    for last in last_answered(history, "txn").into_values() {
+        let Some((read, ops)) = txns.iter().find(|(txn, _)| std::ptr::eq(*txn, last)) else {
+            continue;
+        };
+        let mut written = HashSet::new();
+        for (f, key, value) in ops {
+            if f == "w" {
+                written.insert(key);
+                continue;
+            }
+            let settled = writers
+                .get(key.as_str())
+                .into_iter()
+                .flatten()
+                .all(|writer| {
+                    !std::ptr::eq(txns[*writer].0, *read)
+                        && txns[*writer].0.completed < read.invoked
+                });
+            if settled && !written.contains(key) {
+                finals.entry(key).or_default().insert(&read.node, value);
+            }
+        }
+    }
+
+    let mut last = BTreeMap::new();
+    for (key, reads) in finals {
+        let values: BTreeSet<&str> = reads.values().copied().collect();
+        if values.len() > 1 {
+            anomalies.push(format!(
+                "nodes finally read different values of {key}: {reads:?}"
+            ));
+            continue;
+        }
+        let Some(&(winner, _)) = values.first().and_then(|value| writes.get(&(key, *value))) else {
+            continue;
+        };
+        let committed = writers[key]
+            .iter()
+            .copied()
+            .filter(|writer| txns[*writer].0.reply().is_some())
+            .collect();
+        last.insert(key, (winner, committed));
+    }
+    last
+}
+
+/// The strongly connected components of more than one node, each sorted,
+/// found with Tarjan's algorithm over an explicit stack
+fn cycles(nodes: usize, edges: &BTreeSet<(usize, usize)>) -> Vec<Vec<usize>> {
+    let successors = |node: usize| {
+        edges
+            .range((node, 0)..=(node, usize::MAX))
+            .map(|(_, to)| *to)
+            .collect::<Vec<_>>()
+    };
+    let mut index: Vec<Option<usize>> = vec![None; nodes];
+    let mut low = vec![0; nodes];
+    let mut stack = Vec::new();
+    let mut on_stack = vec![false; nodes];
+    let mut next = 0;
+    let mut components = Vec::new();
+
+    for root in 0..nodes {
+        if index[root].is_some() {
+            continue;
+        }
+        // Nodes being visited, with their successors and how many were seen
+        let mut visiting = vec![(root, successors(root), 0)];
+        index[root] = Some(next);
+        low[root] = next;
+        next += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        while let Some((node, tos, seen)) = visiting.last_mut() {
+            let node = *node;
+            if let Some(&to) = tos.get(*seen) {
+                *seen += 1;
+                match index[to] {
+                    None => {
+                        index[to] = Some(next);
+                        low[to] = next;
+                        next += 1;
+                        stack.push(to);
+                        on_stack[to] = true;
+                        visiting.push((to, successors(to), 0));
+                    }
+                    Some(to_index) if on_stack[to] => low[node] = low[node].min(to_index),
+                    Some(_) => {}
+                }
+                continue;
+            }
+
+            visiting.pop();
+            if let Some((parent, ..)) = visiting.last() {
+                low[*parent] = low[*parent].min(low[node]);
+            }
+            if Some(low[node]) == index[node] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                if component.len() > 1 {
+                    component.sort_unstable();
+                    components.push(component);
+                }
+            }
+        }
+    }
+    components
+}
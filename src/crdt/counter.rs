@@ -0,0 +1,110 @@
+use super::Crdt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A counter that only grows, holding how much each node added
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct GCounter(BTreeMap<String, usize>);
+
+impl GCounter {
+    pub fn increment(&mut self, node: &str, by: usize) {
+        *self.0.entry(node.to_string()).or_default() += by;
+    }
+
+    /// How much `node` added
+    #[must_use]
+    pub fn get(&self, node: &str) -> usize {
+        self.0.get(node).copied().unwrap_or_default()
+    }
+
+    /// Whether every node added at most as much here as in `other`
+    #[must_use]
+    pub fn dominated_by(&self, other: &Self) -> bool {
+        self.0.iter().all(|(node, count)| *count <= other.get(node))
+    }
+}
+
+/// Summarized by itself: it only holds a count per node
+impl Crdt for GCounter {
+    type Value = usize;
+    type Summary = Self;
+
+    fn merge(&mut self, other: &Self) {
+        for (node, count) in &other.0 {
+            let current = self.0.entry(node.clone()).or_default();
+            *current = (*current).max(*count);
+        }
+    }
+
+    fn summarize_into(&self, summary: &mut Self) {
+        summary.merge(self);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        Self(
+            self.0
+                .iter()
+                .filter(|(node, count)| **count > since.get(node))
+                .map(|(node, count)| (node.clone(), *count))
+                .collect(),
+        )
+    }
+
+    fn value(&self) -> usize {
+        self.0.values().sum()
+    }
+}
+
+/// A counter that goes both ways: what was added and what was subtracted are
+/// counted apart
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PnCounter {
+    added: GCounter,
+    subtracted: GCounter,
+}
+
+impl PnCounter {
+    pub fn add(&mut self, node: &str, delta: i64) {
+        let amount = usize::try_from(delta.unsigned_abs()).unwrap_or(usize::MAX);
+        if delta < 0 {
+            self.subtracted.increment(node, amount);
+        } else {
+            self.added.increment(node, amount);
+        }
+    }
+}
+
+impl Crdt for PnCounter {
+    type Value = i64;
+    type Summary = Self;
+
+    fn merge(&mut self, other: &Self) {
+        self.added.merge(&other.added);
+        self.subtracted.merge(&other.subtracted);
+    }
+
+    fn summarize_into(&self, summary: &mut Self) {
+        summary.merge(self);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        Self {
+            added: self.added.delta(&since.added),
+            subtracted: self.subtracted.delta(&since.subtracted),
+        }
+    }
+
+    /// Saturates at the bounds of `i64` rather than wrapping
+    fn value(&self) -> i64 {
+        let total = |counter: &GCounter| -> i128 {
+            counter
+                .0
+                .values()
+                .filter_map(|count| i128::try_from(*count).ok())
+                .sum()
+        };
+        let value = total(&self.added) - total(&self.subtracted);
+        i64::try_from(value).unwrap_or(if value < 0 { i64::MIN } else { i64::MAX })
+    }
+}
@@ -0,0 +1,95 @@
+use super::Crdt;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// Independent CRDTs by key: merging merges each key apart, and deltas only
+/// carry the keys that changed. Sent over the wire as a list of pairs, any
+/// key survives the Maelstrom bodies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Map<K, C> {
+    entries: BTreeMap<K, C>,
+}
+
+impl<K, C> Default for Map<K, C> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord, C: Crdt> Map<K, C> {
+    #[must_use]
+    pub fn get(&self, key: &K) -> Option<&C> {
+        self.entries.get(key)
+    }
+
+    /// The CRDT at `key`, empty if there was none
+    pub fn entry(&mut self, key: K) -> &mut C {
+        self.entries.entry(key).or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &C)> {
+        self.entries.iter()
+    }
+}
+
+impl<K: Serialize, C: Serialize> Serialize for Map<K, C> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(&self.entries)
+    }
+}
+
+impl<'de, K: Ord + Deserialize<'de>, C: Deserialize<'de>> Deserialize<'de> for Map<K, C> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = Vec::<(K, C)>::deserialize(deserializer)?;
+        Ok(Self {
+            entries: entries.into_iter().collect(),
+        })
+    }
+}
+
+impl<K, C> Crdt for Map<K, C>
+where
+    K: Ord + Clone + Serialize + DeserializeOwned,
+    C: Crdt,
+{
+    type Value = BTreeMap<K, C::Value>;
+    type Summary = BTreeMap<K, C::Summary>;
+
+    fn merge(&mut self, other: &Self) {
+        for (key, crdt) in &other.entries {
+            self.entry(key.clone()).merge(crdt);
+        }
+    }
+
+    fn summarize_into(&self, summary: &mut Self::Summary) {
+        for (key, crdt) in &self.entries {
+            crdt.summarize_into(summary.entry(key.clone()).or_default());
+        }
+    }
+
+    fn delta(&self, since: &Self::Summary) -> Self {
+        let empty = C::Summary::default();
+        Self {
+            entries: self
+                .entries
+                .iter()
+                .map(|(key, crdt)| (key, crdt.delta(since.get(key).unwrap_or(&empty))))
+                .filter(|(_, delta)| !delta.is_empty())
+                .map(|(key, delta)| (key.clone(), delta))
+                .collect(),
+        }
+    }
+
+    fn value(&self) -> Self::Value {
+        self.entries
+            .iter()
+            .map(|(key, crdt)| (key.clone(), crdt.value()))
+            .collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.values().all(Crdt::is_empty)
+    }
+}
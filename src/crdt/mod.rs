@@ -0,0 +1,53 @@
+//! State-based CRDTs, which [`crate::gossip`] replicates by sending each
+//! peer the [`Crdt::delta`] it is missing, as told by its [`Crdt::Summary`].
+
+mod counter;
+mod map;
+mod register;
+mod set;
+
+pub use counter::{GCounter, PnCounter};
+pub use map::Map;
+pub use register::{LwwRegister, MvRegister};
+pub use set::{Dot, GSet, OrSet, TwoPSet};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A replicated data type whose replicas converge once they merged each
+/// other's state, in any order and any number of times.
+///
+/// The [`Default`] value is the empty state: merging it changes nothing.
+pub trait Crdt: Clone + Default + PartialEq + Serialize + DeserializeOwned {
+    /// What a replica reads
+    type Value;
+
+    /// Just enough of a state to tell what it lacks, e.g. how many values of
+    /// each node a [`crate::log::Log`] holds. Some types have nothing smaller
+    /// than their state.
+    type Summary: Default;
+
+    /// Keeps everything either state has
+    fn merge(&mut self, other: &Self);
+
+    /// Records in `summary` that its replica merged `self`
+    fn summarize_into(&self, summary: &mut Self::Summary);
+
+    #[must_use]
+    fn summary(&self) -> Self::Summary {
+        let mut summary = Self::Summary::default();
+        self.summarize_into(&mut summary);
+        summary
+    }
+
+    /// The part of `self` a replica summarized by `since` is missing, empty
+    /// if there is none: merging it there has the same effect as merging
+    /// `self`
+    #[must_use]
+    fn delta(&self, since: &Self::Summary) -> Self;
+
+    fn value(&self) -> Self::Value;
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
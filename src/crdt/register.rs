@@ -0,0 +1,157 @@
+use super::{Crdt, GCounter};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// A register where the last write wins. Writes are ordered by a Lamport
+/// timestamp, then by the node that made them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LwwRegister<T> {
+    write: Option<(usize, String, T)>,
+}
+
+impl<T> Default for LwwRegister<T> {
+    fn default() -> Self {
+        Self { write: None }
+    }
+}
+
+impl<T> LwwRegister<T> {
+    /// Writes `value` after every write merged so far
+    pub fn set(&mut self, node: &str, value: T) {
+        let timestamp = self.timestamp().map_or(0, |timestamp| timestamp + 1);
+        self.set_at(timestamp, node, value);
+    }
+
+    /// Writes `value` as of `timestamp`, unless a newer write was merged
+    pub fn set_at(&mut self, timestamp: usize, node: &str, value: T) {
+        let write = Self {
+            write: Some((timestamp, node.to_string(), value)),
+        };
+        if !self.newer_than(&write) {
+            *self = write;
+        }
+    }
+
+    /// Timestamp of the last write, if any
+    #[must_use]
+    pub fn timestamp(&self) -> Option<usize> {
+        self.write.as_ref().map(|(timestamp, ..)| *timestamp)
+    }
+
+    /// Timestamp and node of the last write, which orders writes
+    fn stamp(&self) -> Option<(usize, &str)> {
+        self.write
+            .as_ref()
+            .map(|(timestamp, node, _)| (*timestamp, node.as_str()))
+    }
+
+    fn newer_than(&self, other: &Self) -> bool {
+        self.stamp() > other.stamp()
+    }
+}
+
+fn stamp_of(summary: &Option<(usize, String)>) -> Option<(usize, &str)> {
+    summary
+        .as_ref()
+        .map(|(timestamp, node)| (*timestamp, node.as_str()))
+}
+
+/// Summarized by the timestamp and node of its last write
+impl<T: Clone + PartialEq + Serialize + DeserializeOwned> Crdt for LwwRegister<T> {
+    type Value = Option<T>;
+    type Summary = Option<(usize, String)>;
+
+    fn merge(&mut self, other: &Self) {
+        if other.newer_than(self) {
+            self.write = other.write.clone();
+        }
+    }
+
+    fn summarize_into(&self, summary: &mut Self::Summary) {
+        if let Some((timestamp, node)) = self
+            .stamp()
+            .filter(|stamp| Some(*stamp) > stamp_of(summary))
+        {
+            *summary = Some((timestamp, node.to_string()));
+        }
+    }
+
+    fn delta(&self, since: &Self::Summary) -> Self {
+        if self.stamp() > stamp_of(since) {
+            self.clone()
+        } else {
+            Self::default()
+        }
+    }
+
+    fn value(&self) -> Option<T> {
+        self.write.as_ref().map(|(.., value)| value.clone())
+    }
+}
+
+/// A register that keeps every concurrent write: reads return them all until
+/// a write that saw them replaces them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MvRegister<T> {
+    /// Writes none of the others saw, each with the writes it saw, in the
+    /// order of their clocks
+    writes: Vec<(GCounter, T)>,
+}
+
+impl<T> Default for MvRegister<T> {
+    fn default() -> Self {
+        Self { writes: Vec::new() }
+    }
+}
+
+impl<T: Clone + PartialEq> MvRegister<T> {
+    /// Replaces every write merged so far with `value`
+    pub fn set(&mut self, node: &str, value: T) {
+        let mut clock = GCounter::default();
+        for (seen, _) in &self.writes {
+            clock.merge(seen);
+        }
+        clock.increment(node, 1);
+        self.writes = vec![(clock, value)];
+    }
+}
+
+impl<T: Clone + PartialEq + Serialize + DeserializeOwned> Crdt for MvRegister<T> {
+    type Value = Vec<T>;
+    type Summary = Self;
+
+    fn merge(&mut self, other: &Self) {
+        let mut writes = self.writes.clone();
+        for write in &other.writes {
+            if !writes.contains(write) {
+                writes.push(write.clone());
+            }
+        }
+        writes.sort_by(|(clock, _), (other, _)| clock.cmp(other));
+        // Drops the writes another one saw
+        self.writes = writes
+            .iter()
+            .filter(|(clock, _)| {
+                !writes
+                    .iter()
+                    .any(|(other, _)| other != clock && clock.dominated_by(other))
+            })
+            .cloned()
+            .collect();
+    }
+
+    fn summarize_into(&self, summary: &mut Self) {
+        summary.merge(self);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        if self.writes.iter().all(|write| since.writes.contains(write)) {
+            Self::default()
+        } else {
+            self.clone()
+        }
+    }
+
+    fn value(&self) -> Vec<T> {
+        self.writes.iter().map(|(_, value)| value.clone()).collect()
+    }
+}
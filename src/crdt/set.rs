@@ -0,0 +1,204 @@
+use super::Crdt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// A set that only grows
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct GSet<T: Ord>(BTreeSet<T>);
+
+impl<T: Ord> Default for GSet<T> {
+    fn default() -> Self {
+        Self(BTreeSet::new())
+    }
+}
+
+impl<T: Ord> GSet<T> {
+    pub fn insert(&mut self, value: T) {
+        self.0.insert(value);
+    }
+
+    #[must_use]
+    pub fn contains(&self, value: &T) -> bool {
+        self.0.contains(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.0.iter()
+    }
+}
+
+impl<T: Ord + Clone + Serialize + DeserializeOwned> Crdt for GSet<T> {
+    type Value = BTreeSet<T>;
+    type Summary = Self;
+
+    fn merge(&mut self, other: &Self) {
+        self.0.extend(other.0.iter().cloned());
+    }
+
+    fn summarize_into(&self, summary: &mut Self) {
+        summary.merge(self);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        Self(self.0.difference(&since.0).cloned().collect())
+    }
+
+    fn value(&self) -> BTreeSet<T> {
+        self.0.clone()
+    }
+}
+
+/// A set where removed values stay removed: adding them again has no effect
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TwoPSet<T: Ord> {
+    added: GSet<T>,
+    removed: GSet<T>,
+}
+
+impl<T: Ord> Default for TwoPSet<T> {
+    fn default() -> Self {
+        Self {
+            added: GSet::default(),
+            removed: GSet::default(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> TwoPSet<T> {
+    pub fn insert(&mut self, value: T) {
+        self.added.insert(value);
+    }
+
+    /// Removes `value` for good, if it was added
+    pub fn remove(&mut self, value: &T) {
+        if self.added.contains(value) {
+            self.removed.insert(value.clone());
+        }
+    }
+
+    #[must_use]
+    pub fn contains(&self, value: &T) -> bool {
+        self.added.contains(value) && !self.removed.contains(value)
+    }
+}
+
+impl<T: Ord + Clone + Serialize + DeserializeOwned> Crdt for TwoPSet<T> {
+    type Value = BTreeSet<T>;
+    type Summary = Self;
+
+    fn merge(&mut self, other: &Self) {
+        self.added.merge(&other.added);
+        self.removed.merge(&other.removed);
+    }
+
+    fn summarize_into(&self, summary: &mut Self) {
+        summary.merge(self);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        Self {
+            added: self.added.delta(&since.added),
+            removed: self.removed.delta(&since.removed),
+        }
+    }
+
+    fn value(&self) -> BTreeSet<T> {
+        self.added
+            .iter()
+            .filter(|value| !self.removed.contains(value))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Identifies one addition: the `counter`-th one made by `node`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Dot {
+    pub node: String,
+    pub counter: usize,
+}
+
+/// A set where an addition wins over the removals that did not see it. Each
+/// addition is tagged with a unique [`Dot`], and a removal only removes the
+/// tags it saw.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct OrSet<T: Ord> {
+    added: BTreeSet<(T, Dot)>,
+    removed: BTreeSet<Dot>,
+}
+
+impl<T: Ord> Default for OrSet<T> {
+    fn default() -> Self {
+        Self {
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> OrSet<T> {
+    pub fn insert(&mut self, node: &str, value: T) {
+        let counter = self
+            .added
+            .iter()
+            .map(|(_, dot)| dot)
+            .chain(&self.removed)
+            .filter(|dot| dot.node == node)
+            .map(|dot| dot.counter + 1)
+            .max()
+            .unwrap_or_default();
+        let dot = Dot {
+            node: node.to_string(),
+            counter,
+        };
+        self.added.insert((value, dot));
+    }
+
+    /// Removes the additions of `value` seen so far
+    pub fn remove(&mut self, value: &T) {
+        let seen: Vec<_> = self
+            .added
+            .iter()
+            .filter(|(added, dot)| added == value && !self.removed.contains(dot))
+            .map(|(_, dot)| dot.clone())
+            .collect();
+        self.removed.extend(seen);
+    }
+
+    #[must_use]
+    pub fn contains(&self, value: &T) -> bool {
+        self.added
+            .iter()
+            .any(|(added, dot)| added == value && !self.removed.contains(dot))
+    }
+}
+
+impl<T: Ord + Clone + Serialize + DeserializeOwned> Crdt for OrSet<T> {
+    type Value = BTreeSet<T>;
+    type Summary = Self;
+
+    fn merge(&mut self, other: &Self) {
+        self.added.extend(other.added.iter().cloned());
+        self.removed.extend(other.removed.iter().cloned());
+    }
+
+    fn summarize_into(&self, summary: &mut Self) {
+        summary.merge(self);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        Self {
+            added: self.added.difference(&since.added).cloned().collect(),
+            removed: self.removed.difference(&since.removed).cloned().collect(),
+        }
+    }
+
+    fn value(&self) -> BTreeSet<T> {
+        self.added
+            .iter()
+            .filter(|(_, dot)| !self.removed.contains(dot))
+            .map(|(value, _)| value.clone())
+            .collect()
+    }
+}
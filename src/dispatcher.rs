@@ -0,0 +1,161 @@
+use crate::sender::{Replies, BATCH};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver};
+
+/// Capacity of every handler queue, and of the backlog in front of it
+pub const QUEUE_CAPACITY: usize = 128;
+
+#[derive(Default, Debug)]
+pub struct DispatchStats {
+    /// Messages that found their handler's queue full and had to wait
+    pub lagged: AtomicUsize,
+    /// Messages nobody registered for
+    pub unroutable: AtomicUsize,
+    /// Messages that found their handler's backlog full too
+    pub dropped: AtomicUsize,
+}
+
+/// Routes each incoming message to the one handler registered for its `type`.
+///
+/// Batches are unpacked into the messages they carry. Replies to pending
+/// `rpc` calls are resolved first. Everything else goes to
+/// the handler registered for the message type, or to the fallback handler
+/// if there is none. Queues are bounded, and so is the backlog each handler
+/// has in front of its queue: a slow handler fills its backlog, then loses
+/// the messages that do not fit, but never holds up the others nor the
+/// replies they wait for.
+pub struct Dispatcher {
+    replies: Replies,
+    routes: HashMap<String, mpsc::Sender<Value>>,
+    fallback: Option<mpsc::Sender<Value>>,
+    /// Keeps every queue open until the dispatcher is done, even those no
+    /// message type is routed to
+    queues: Vec<mpsc::Sender<Value>>,
+    stats: Arc<DispatchStats>,
+}
+
+impl Dispatcher {
+    #[must_use]
+    pub fn new(replies: Replies) -> Self {
+        Self {
+            replies,
+            routes: HashMap::new(),
+            fallback: None,
+            queues: Vec::new(),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Returns the queue that will receive every message of the given types
+    ///
+    /// # Panics
+    ///
+    /// - if called outside a Tokio runtime
+    pub fn register(&mut self, msg_types: &[&str]) -> Receiver<Value> {
+        let (tx, rx) = self.queue();
+        for msg_type in msg_types {
+            if self
+                .routes
+                .insert((*msg_type).to_string(), tx.clone())
+                .is_some()
+            {
+                tracing::warn!(msg_type, "Message type registered twice");
+            }
+        }
+        self.queues.push(tx);
+        rx
+    }
+
+    /// Returns the queue that will receive messages no one else registered for
+    ///
+    /// # Panics
+    ///
+    /// - if called outside a Tokio runtime
+    pub fn register_fallback(&mut self) -> Receiver<Value> {
+        let (tx, rx) = self.queue();
+        self.fallback = Some(tx);
+        rx
+    }
+
+    /// Creates a handler queue, fed from its backlog by a task of its own
+    fn queue(&self) -> (mpsc::Sender<Value>, Receiver<Value>) {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let (backlog, mut pending) = mpsc::channel::<Value>(QUEUE_CAPACITY);
+        let stats = self.stats.clone();
+        tokio::spawn(async move {
+            while let Some(value) = pending.recv().await {
+                let msg_type = value["body"]["type"].clone();
+                let value = match tx.try_send(value) {
+                    Ok(()) => continue,
+                    Err(TrySendError::Full(value)) => {
+                        let lagged = stats.lagged.fetch_add(1, Ordering::Relaxed) + 1;
+                        tracing::warn!(%msg_type, lagged, "Handler lagging");
+                        value
+                    }
+                    Err(TrySendError::Closed(value)) => value,
+                };
+                if tx.send(value).await.is_err() {
+                    let unroutable = stats.unroutable.fetch_add(1, Ordering::Relaxed) + 1;
+                    tracing::warn!(%msg_type, unroutable, "Handler is gone");
+                }
+            }
+        });
+        (backlog, rx)
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> Arc<DispatchStats> {
+        self.stats.clone()
+    }
+
+    /// Routes `value`, or every message in it if it is a batch
+    pub fn dispatch(&self, mut value: Value) {
+        if value["body"]["type"] != BATCH {
+            return self.route(value);
+        }
+        let Value::Array(messages) = value["body"]["messages"].take() else {
+            tracing::warn!("Batch without messages");
+            return;
+        };
+        for message in messages {
+            self.route(message);
+        }
+    }
+
+    fn route(&self, value: Value) {
+        let Some(value) = self.replies.resolve(value) else {
+            return;
+        };
+
+        let msg_type = value["body"]["type"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let Some(route) = self.routes.get(&msg_type).or(self.fallback.as_ref()) else {
+            let unroutable = self.stats.unroutable.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::warn!(msg_type, unroutable, "No handler for message");
+            return;
+        };
+
+        // Backlogs are only closed once the dispatcher is dropped
+        if let Err(TrySendError::Full(_)) = route.try_send(value) {
+            let dropped = self.stats.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::warn!(msg_type, dropped, "Handler backlog full, dropping message");
+        }
+    }
+
+    /// Dispatches everything coming from `input`. Handler queues are closed
+    /// once `input` is and their backlogs are delivered.
+    pub async fn run(self, mut input: Receiver<Value>) {
+        while let Some(value) = input.recv().await {
+            self.dispatch(value);
+        }
+    }
+}
@@ -1,173 +1,197 @@
+use crate::crdt::Crdt;
 use crate::init_state::{InitState, Initable};
-use crate::log::Log;
+use crate::membership::Status;
 use crate::message::{Body, Message};
 use crate::sender::Sender;
-use crate::traits::store::Store;
+use crate::traits::replica::Replica;
 use crate::wait_for_message_then;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{
-    collections::HashMap,
+    collections::BTreeMap,
     io::Write,
     sync::{Arc, Mutex},
     time::Duration,
 };
-use tokio::sync::broadcast::Receiver;
+use tokio::{sync::mpsc::Receiver, time::Instant};
+
+/// Message types handled by [`handle`]
+pub const MESSAGE_TYPES: &[&str] = &["gossip", "gossip_ok"];
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
-enum GossipMessages<T: Serialize + Clone + Eq> {
-    Gossip { seen: HashMap<String, T> },
-    GossipOk { seen: HashMap<String, T> },
+enum GossipMessages<C> {
+    /// What the receiver was missing at last notice
+    Gossip { delta: C },
+    /// The gossip was merged
+    GossipOk,
 }
 
-struct GossipState<T> {
-    known: HashMap<String, HashMap<String, T>>,
+/// Retransmissions to a silent peer back off up to `2^MAX_DOUBLINGS` gossip
+/// periods apart
+const MAX_DOUBLINGS: u32 = 5;
+
+#[derive(Default)]
+struct Peer<C: Crdt> {
+    /// What the peer is known to have
+    acked: C::Summary,
+    /// `msg_id` of the gossip waiting for an ack, when to give up on it, and
+    /// what it carries
+    in_flight: Option<(usize, Instant, C)>,
+    /// Gossips in a row the peer did not acknowledge in time
+    unanswered: u32,
 }
 
-impl<T> GossipState<T> {
-    fn from_state<V: Initable>(state: &InitState<V>) -> Self {
-        Self {
-            known: state
-                .get_init()
-                .node_ids
-                .iter()
-                .map(|node| (node.clone(), HashMap::new()))
-                .collect(),
+/// A [`Crdt::Summary`] of what every peer is known to have. Each gossip only
+/// carries the [`Crdt::delta`] the peer is missing. Only one gossip per peer
+/// is in flight: it is retransmitted when its ack does not come back in time,
+/// waiting twice as long after every miss.
+struct GossipState<C: Crdt> {
+    periodicity: Duration,
+    peers: BTreeMap<String, Peer<C>>,
+}
+
+impl<C: Crdt> GossipState<C> {
+    fn backoff(&self, unanswered: u32) -> Duration {
+        self.periodicity * 2u32.pow(unanswered.min(MAX_DOUBLINGS))
+    }
+
+    /// `peer` sent us `delta`, so it has it. It is also reachable: if it was
+    /// not answering, retry right away instead of waiting out the backoff.
+    fn gossiped_by(&mut self, peer: &str, delta: &C) {
+        let peer = self.peers.entry(peer.to_string()).or_default();
+        delta.summarize_into(&mut peer.acked);
+        if peer.unanswered > 0 {
+            peer.unanswered = 0;
+            peer.in_flight = None;
+        }
+    }
+
+    /// `peer` acknowledged the gossip `in_reply_to`. Late acks are ignored:
+    /// the gossip in flight carries whatever they acknowledged.
+    fn acknowledged_by(&mut self, peer: &str, in_reply_to: Option<usize>) {
+        let peer = self.peers.entry(peer.to_string()).or_default();
+        let Some((msg_id, _, ref sent)) = peer.in_flight else {
+            return;
+        };
+        if Some(msg_id) != in_reply_to {
+            return;
         }
+        sent.summarize_into(&mut peer.acked);
+        peer.in_flight = None;
+        peer.unanswered = 0;
     }
 }
 
-fn handle_msg<T, StoreImpl, W>(
-    input: &Message<GossipMessages<T>>,
+fn handle_msg<StoreImpl, W>(
+    input: &Message<GossipMessages<StoreImpl::Crdt>>,
     output: &Arc<Mutex<Sender<W>>>,
     state: &Arc<Mutex<InitState<StoreImpl>>>,
-    known_ctx: &Arc<Mutex<GossipState<T>>>,
+    gossip_state: &Arc<Mutex<GossipState<StoreImpl::Crdt>>>,
 ) where
     W: Write,
-    T: Clone + Serialize + Eq + DeserializeOwned + Send + 'static,
-    StoreImpl: Store<T> + Send + Initable + 'static,
+    StoreImpl: Replica + Initable,
 {
     match input.body.msg_type {
-        GossipMessages::Gossip { ref seen } => {
-            let mut state = state.lock().unwrap();
-            let known_by_me = state.clone();
-            seen.clone().iter().for_each(|(key, val)| {
-                if let Some(val) = state.insert_with_key(key, val) {
-                    state.new_value(val);
-                }
-            });
-            std::mem::drop(state);
-            let mut known_ctx = known_ctx.lock().unwrap();
-            if !known_ctx.known.contains_key(&input.src) {
-                known_ctx.known.insert(input.src.clone(), HashMap::new());
-            }
-            known_ctx
-                .known
-                .get_mut(&input.src)
-                .expect("got gossip")
-                .extend(seen.clone());
-            let mut ack_messages: HashMap<String, _> = known_by_me
-                .clone()
-                .into_iter()
-                .filter(|(x, _)| !known_ctx.known[&input.src].contains_key(x))
-                .collect();
-            std::mem::drop(known_ctx);
-            ack_messages.extend(seen.clone());
-            let _ = output.lock().unwrap().reply(
-                input.clone(),
-                GossipMessages::GossipOk { seen: ack_messages },
-            );
+        GossipMessages::Gossip { ref delta } => {
+            state.lock().unwrap().merge(delta);
+            gossip_state.lock().unwrap().gossiped_by(&input.src, delta);
+            let _ = output
+                .lock()
+                .unwrap()
+                .reply(input, GossipMessages::<StoreImpl::Crdt>::GossipOk);
         }
-        GossipMessages::GossipOk { ref seen } => {
-            let mut state = state.lock().unwrap();
-            seen.clone().iter().for_each(|(key, val)| {
-                if let Some(val) = state.insert_with_key(key, val) {
-                    state.new_value(val);
-                }
-            });
-            std::mem::drop(state);
-            let mut known_ctx = known_ctx.lock().unwrap();
-            if !known_ctx.known.contains_key(&input.src) {
-                known_ctx.known.insert(input.src.clone(), HashMap::new());
-            }
-            known_ctx
-                .known
-                .get_mut(&input.src)
-                .expect("got gossipok")
-                .extend(seen.clone());
+        GossipMessages::GossipOk => {
+            gossip_state
+                .lock()
+                .unwrap()
+                .acknowledged_by(&input.src, input.body.in_reply_to);
         }
     };
 }
 
-fn gossip<T, StoreImpl, W>(
+fn gossip<StoreImpl, W>(
     output: &Arc<Mutex<Sender<W>>>,
     state: &Arc<Mutex<InitState<StoreImpl>>>,
-    known_ctx: &Arc<Mutex<GossipState<T>>>,
+    gossip_state: &Arc<Mutex<GossipState<StoreImpl::Crdt>>>,
 ) where
     W: Write,
-    T: Clone + Serialize + Eq + DeserializeOwned + Send + 'static,
-    StoreImpl: Store<T> + Send + Initable + 'static,
+    StoreImpl: Replica + Initable,
 {
     let state = state.lock().unwrap();
     let node = &state.get_init().node_id;
-    let known_by_me: &Log<T> = &state;
+    let mut gossip_state = gossip_state.lock().unwrap();
+    let now = Instant::now();
     for n in state.get_neighbors() {
-        if n == node {
+        if n == node || state.membership().status(n) == Some(Status::Dead) {
             continue;
         }
 
-        let mut known_ctx = known_ctx.lock().unwrap();
-        if !known_ctx.known.contains_key(n) {
-            known_ctx.known.insert(n.clone(), HashMap::new());
+        let peer = gossip_state.peers.entry(n.clone()).or_default();
+        if let Some((_, retry_at, _)) = peer.in_flight {
+            if now < retry_at {
+                continue;
+            }
+            peer.unanswered += 1;
+            if peer.unanswered == MAX_DOUBLINGS {
+                tracing::debug!(peer = n, "Peer unresponsive, backing off");
+            }
         }
-        let seen: HashMap<_, _> = known_by_me
-            .clone()
-            .into_iter()
-            .filter(|(ref x, _)| !known_ctx.known[n].contains_key(x))
-            .collect();
 
-        std::mem::drop(known_ctx);
-
-        if seen.is_empty() {
+        let delta = state.crdt().delta(&peer.acked);
+        if delta.is_empty() {
             tracing::trace!("Nothing to gossip");
+            peer.in_flight = None;
             continue;
         }
 
-        let _ = output.lock().unwrap().send(
+        let unanswered = peer.unanswered;
+        let backoff = gossip_state.backoff(unanswered);
+        let mut output = output.lock().unwrap();
+        let msg_id = output.get_id();
+        let sent = output.send(
             Message {
                 src: node.clone(),
                 dest: n.clone(),
                 body: Body {
                     msg_id: None,
                     in_reply_to: None,
-                    msg_type: GossipMessages::Gossip { seen },
+                    msg_type: GossipMessages::Gossip {
+                        delta: delta.clone(),
+                    },
                 },
             },
             true,
         );
+        if sent.is_ok() {
+            gossip_state.peers.entry(n.clone()).or_default().in_flight =
+                Some((msg_id, now + backoff, delta));
+        }
     }
 }
 
 /// # Panics
 ///
 /// - if locks are poisoned
-pub async fn handle<T, StoreImpl, W>(
+pub async fn handle<StoreImpl, W>(
     mut rx: Receiver<Value>,
     output: Arc<Mutex<Sender<W>>>,
     state: Arc<Mutex<InitState<StoreImpl>>>,
     gossip_periodicity: Duration,
 ) where
     W: Write,
-    T: Clone + Serialize + Eq + DeserializeOwned + Send + 'static,
-    StoreImpl: Store<T> + Send + Initable + 'static,
+    StoreImpl: Replica + Initable,
 {
-    let known_ctx = Arc::new(Mutex::new(GossipState::from_state(&state.lock().unwrap())));
+    let gossip_state = Arc::new(Mutex::new(GossipState {
+        periodicity: gossip_periodicity,
+        peers: BTreeMap::new(),
+    }));
     loop {
         tokio::select! {
+            biased;
             result = wait_for_message_then(&mut rx, |msg| {
-                handle_msg(&msg, &output, &state, &known_ctx);
+                handle_msg(&msg, &output, &state, &gossip_state);
                 Ok(())
             }) => {
                 match result {
@@ -176,7 +200,7 @@ pub async fn handle<T, StoreImpl, W>(
                 }
             }
             () = tokio::time::sleep(gossip_periodicity) => {
-                gossip(&output, &state, &known_ctx);
+                gossip(&output, &state, &gossip_state);
             }
         }
     }
@@ -0,0 +1,140 @@
+//! Record of what clients asked the nodes and what they got back, to be
+//! checked once a run is over.
+
+use crate::message::MaelstromError;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    future::Future,
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok {
+        reply: Value,
+    },
+    /// The operation did not take place
+    Fail {
+        error: MaelstromError,
+    },
+    /// The operation may or may not have taken place
+    Info {
+        error: MaelstromError,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Operation {
+    /// Client that issued the request
+    pub process: String,
+    pub node: String,
+    pub request: Value,
+    pub outcome: Outcome,
+    /// Since the history was created
+    pub invoked: Duration,
+    pub completed: Duration,
+}
+
+impl Operation {
+    /// The `type` of the request
+    #[must_use]
+    pub fn request_type(&self) -> &str {
+        self.request["type"].as_str().unwrap_or_default()
+    }
+
+    /// The reply, if the operation succeeded
+    #[must_use]
+    pub fn reply(&self) -> Option<&Value> {
+        match &self.outcome {
+            Outcome::Ok { reply } => Some(reply),
+            Outcome::Fail { .. } | Outcome::Info { .. } => None,
+        }
+    }
+}
+
+/// Shared between all clients of a run, operations are kept in completion
+/// order
+#[derive(Clone)]
+pub struct History {
+    start: Instant,
+    operations: Arc<Mutex<Vec<Operation>>>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            operations: Arc::default(),
+        }
+    }
+}
+
+impl History {
+    /// Awaits `call`, the request `process` sent to `node`, and records its
+    /// outcome
+    ///
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    pub async fn record(
+        &self,
+        process: &str,
+        node: &str,
+        request: Value,
+        call: impl Future<Output = Result<Value>>,
+    ) -> Outcome {
+        let invoked = self.start.elapsed();
+        let outcome = match call.await {
+            Ok(reply) => Outcome::Ok { reply },
+            Err(error) => {
+                let error = MaelstromError::from(error);
+                if error.is_definite() {
+                    Outcome::Fail { error }
+                } else {
+                    Outcome::Info { error }
+                }
+            }
+        };
+        self.operations.lock().unwrap().push(Operation {
+            process: process.to_string(),
+            node: node.to_string(),
+            request,
+            outcome: outcome.clone(),
+            invoked,
+            completed: self.start.elapsed(),
+        });
+        outcome
+    }
+
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    #[must_use]
+    pub fn operations(&self) -> Vec<Operation> {
+        self.operations.lock().unwrap().clone()
+    }
+
+    /// Writes one JSON operation per line
+    ///
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    ///
+    /// # Errors
+    ///
+    /// - if writing fails
+    pub fn write_to(&self, mut writer: impl Write) -> Result<()> {
+        for operation in self.operations.lock().unwrap().iter() {
+            serde_json::to_writer(&mut writer, operation)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
@@ -1,19 +1,20 @@
-use crate::{message::Message, sender::Sender};
+use crate::{membership::Membership, message::Message, sender::Sender};
 use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{
-    collections::HashSet,
+    collections::BTreeSet,
     io::Write,
     ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
 };
-use tokio::sync::broadcast::Receiver;
+use tokio::sync::mpsc::Receiver;
 
 pub struct InitState<T: Initable> {
     init: Init,
     state: T,
-    neighborhood: HashSet<String>,
+    neighborhood: BTreeSet<String>,
+    membership: Membership,
 }
 
 impl<T: Initable> InitState<T> {
@@ -22,11 +23,12 @@ impl<T: Initable> InitState<T> {
         Self {
             init: init.clone(),
             state: T::with_init(init.clone()),
+            membership: Membership::new(&init),
             neighborhood: init.node_ids,
         }
     }
 
-    pub fn get_neighbors(&self) -> &HashSet<String> {
+    pub fn get_neighbors(&self) -> &BTreeSet<String> {
         &self.neighborhood
     }
 
@@ -34,6 +36,20 @@ impl<T: Initable> InitState<T> {
         self.neighborhood.insert(val.to_string());
     }
 
+    /// Replaces the neighborhood, which starts with every node
+    pub fn set_neighbors(&mut self, neighbors: BTreeSet<String>) {
+        self.neighborhood = neighbors;
+    }
+
+    /// Which nodes are alive, kept up to date by [`crate::membership::handle`]
+    pub fn membership(&self) -> &Membership {
+        &self.membership
+    }
+
+    pub fn membership_mut(&mut self) -> &mut Membership {
+        &mut self.membership
+    }
+
     pub fn get_init(&self) -> &Init {
         &self.init
     }
@@ -56,7 +72,7 @@ impl<T: Initable> DerefMut for InitState<T> {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Init {
     pub node_id: String,
-    pub node_ids: HashSet<String>,
+    pub node_ids: BTreeSet<String>,
 }
 
 pub trait Initable {
@@ -88,7 +104,7 @@ where
     W: Write,
     StateImpl: Initable + Send + 'static,
 {
-    let value = rx.recv().await.context("Error receiving")?;
+    let value = rx.recv().await.context("Channel closed before init")?;
     let input =
         serde_json::from_value::<Message<InitMessage>>(value).context("Parsing init message")?;
 
@@ -96,11 +112,12 @@ where
         bail!("Received a message that does not match Init (InitOk?)")
     };
 
+    let mut output = output.lock().unwrap();
+    output.set_node_id(&init.node_id);
     output
-        .lock()
-        .unwrap()
-        .reply(input.clone(), InitMessage::InitOk)
+        .reply(&input, InitMessage::InitOk)
         .context("Confirm init message")?;
+    std::mem::drop(output);
 
     Ok(Arc::new(Mutex::new(InitState::<StateImpl>::from_init(
         init.clone(),
@@ -0,0 +1,326 @@
+use crate::message::{Body, ErrorCode, MaelstromError, Message};
+use crate::sender::Sender;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    io::Write,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum KvMessage {
+    Read {
+        key: Value,
+    },
+    ReadOk {
+        value: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    WriteOk,
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+}
+
+/// One of the KV services built into Maelstrom
+pub trait Service {
+    const NAME: &'static str;
+}
+
+pub struct Seq;
+pub struct Lin;
+pub struct Lww;
+
+impl Service for Seq {
+    const NAME: &'static str = "seq-kv";
+}
+
+impl Service for Lin {
+    const NAME: &'static str = "lin-kv";
+}
+
+impl Service for Lww {
+    const NAME: &'static str = "lww-kv";
+}
+
+pub type SeqKv<W> = Kv<Seq, W>;
+pub type LinKv<W> = Kv<Lin, W>;
+pub type LwwKv<W> = Kv<Lww, W>;
+
+#[derive(Debug)]
+pub enum KvError {
+    KeyDoesNotExist,
+    PreconditionFailed,
+    Failed(MaelstromError),
+}
+
+impl KvError {
+    /// Whether the operation is known not to have taken place
+    #[must_use]
+    pub fn is_definite(&self) -> bool {
+        match self {
+            Self::KeyDoesNotExist | Self::PreconditionFailed => true,
+            Self::Failed(error) => error.is_definite(),
+        }
+    }
+}
+
+impl Display for KvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KeyDoesNotExist => write!(f, "key does not exist"),
+            Self::PreconditionFailed => write!(f, "precondition failed"),
+            Self::Failed(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+impl From<anyhow::Error> for KvError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = MaelstromError::from(error);
+        match error.code {
+            ErrorCode::KeyDoesNotExist => Self::KeyDoesNotExist,
+            ErrorCode::PreconditionFailed => Self::PreconditionFailed,
+            _ => Self::Failed(error),
+        }
+    }
+}
+
+impl From<KvError> for MaelstromError {
+    fn from(error: KvError) -> Self {
+        match error {
+            KvError::KeyDoesNotExist => Self::new(ErrorCode::KeyDoesNotExist, error.to_string()),
+            KvError::PreconditionFailed => {
+                Self::new(ErrorCode::PreconditionFailed, error.to_string())
+            }
+            KvError::Failed(error) => error,
+        }
+    }
+}
+
+/// Values that do not (de)serialize are our own fault, not the client's,
+/// and a reply that cannot be decoded may come from a call that took place
+impl From<serde_json::Error> for KvError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Failed(MaelstromError::new(ErrorCode::Crash, error.to_string()))
+    }
+}
+
+/// Client for a Maelstrom KV service. Replies only arrive when something
+/// resolves them for `output`, as the [`crate::dispatcher::Dispatcher`] does.
+pub struct Kv<S: Service, W: Write> {
+    output: Arc<Mutex<Sender<W>>>,
+    timeout: Duration,
+    service: PhantomData<S>,
+}
+
+impl<S: Service, W: Write> Clone for Kv<S, W> {
+    fn clone(&self) -> Self {
+        Self {
+            output: self.output.clone(),
+            timeout: self.timeout,
+            service: PhantomData,
+        }
+    }
+}
+
+impl<S: Service, W: Write> Kv<S, W> {
+    #[must_use]
+    pub fn new(output: Arc<Mutex<Sender<W>>>) -> Self {
+        Self {
+            output,
+            timeout: Duration::from_secs(1),
+            service: PhantomData,
+        }
+    }
+
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    ///
+    /// # Errors
+    ///
+    /// - [`KvError::KeyDoesNotExist`] if the key was never written
+    /// - the service failed or timed out
+    pub async fn read<V: DeserializeOwned>(&self, key: impl Serialize) -> Result<V, KvError> {
+        let request = KvMessage::Read {
+            key: serde_json::to_value(key)?,
+        };
+        match self.call(request).await? {
+            KvMessage::ReadOk { value } => Ok(serde_json::from_value(value)?),
+            _ => Err(Self::unexpected()),
+        }
+    }
+
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    ///
+    /// # Errors
+    ///
+    /// - the service failed or timed out
+    pub async fn write(&self, key: impl Serialize, value: impl Serialize) -> Result<(), KvError> {
+        let request = KvMessage::Write {
+            key: serde_json::to_value(key)?,
+            value: serde_json::to_value(value)?,
+        };
+        match self.call(request).await? {
+            KvMessage::WriteOk => Ok(()),
+            _ => Err(Self::unexpected()),
+        }
+    }
+
+    /// Sets `key` to `to` if it currently holds `from`
+    ///
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    ///
+    /// # Errors
+    ///
+    /// - [`KvError::PreconditionFailed`] if the key does not hold `from`
+    /// - [`KvError::KeyDoesNotExist`] if the key is missing and
+    ///   `create_if_not_exists` is not set
+    /// - the service failed or timed out
+    pub async fn cas(
+        &self,
+        key: impl Serialize,
+        from: impl Serialize,
+        to: impl Serialize,
+        create_if_not_exists: bool,
+    ) -> Result<(), KvError> {
+        let request = KvMessage::Cas {
+            key: serde_json::to_value(key)?,
+            from: serde_json::to_value(from)?,
+            to: serde_json::to_value(to)?,
+            create_if_not_exists,
+        };
+        match self.call(request).await? {
+            KvMessage::CasOk => Ok(()),
+            _ => Err(Self::unexpected()),
+        }
+    }
+
+    async fn call(&self, request: KvMessage) -> Result<KvMessage, KvError> {
+        let reply =
+            self.output
+                .lock()
+                .unwrap()
+                .rpc_timeout::<_, KvMessage>(S::NAME, request, self.timeout);
+        Ok(reply.await?.body.msg_type)
+    }
+
+    fn unexpected() -> KvError {
+        KvError::Failed(MaelstromError::new(
+            ErrorCode::Crash,
+            format!("Unexpected reply from {}", S::NAME),
+        ))
+    }
+}
+
+/// In-memory stand-in for the Maelstrom KV services. Every operation is
+/// applied atomically, so it is valid (if stricter than needed) for all of
+/// `seq-kv`, `lin-kv` and `lww-kv`.
+#[derive(Default)]
+pub struct LocalKv {
+    // JSON values are not hashable, keys are stored in their serialized form
+    values: HashMap<String, Value>,
+}
+
+impl LocalKv {
+    /// # Errors
+    ///
+    /// - the Maelstrom error the real service would answer with
+    pub fn apply(&mut self, request: &KvMessage) -> Result<KvMessage, MaelstromError> {
+        match request {
+            KvMessage::Read { key } => self
+                .values
+                .get(&key.to_string())
+                .map(|value| KvMessage::ReadOk {
+                    value: value.clone(),
+                })
+                .ok_or_else(|| {
+                    MaelstromError::new(ErrorCode::KeyDoesNotExist, format!("{key} not found"))
+                }),
+            KvMessage::Write { key, value } => {
+                self.values.insert(key.to_string(), value.clone());
+                Ok(KvMessage::WriteOk)
+            }
+            KvMessage::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.values.get_mut(&key.to_string()) {
+                Some(current) if current == from => {
+                    *current = to.clone();
+                    Ok(KvMessage::CasOk)
+                }
+                Some(current) => Err(MaelstromError::new(
+                    ErrorCode::PreconditionFailed,
+                    format!("expected {from}, found {current}"),
+                )),
+                None if *create_if_not_exists => {
+                    self.values.insert(key.to_string(), to.clone());
+                    Ok(KvMessage::CasOk)
+                }
+                None => Err(MaelstromError::new(
+                    ErrorCode::KeyDoesNotExist,
+                    format!("{key} not found"),
+                )),
+            },
+            KvMessage::ReadOk { .. } | KvMessage::WriteOk | KvMessage::CasOk => Err(
+                MaelstromError::new(ErrorCode::NotSupported, "not a kv request"),
+            ),
+        }
+    }
+
+    /// Builds the reply a KV service would send for `request`, or `None` when
+    /// it cannot be answered (no `msg_id`)
+    ///
+    /// # Panics
+    ///
+    /// - if replies fail to serialize
+    pub fn reply(&mut self, request: &Message<KvMessage>) -> Option<Value> {
+        let in_reply_to = request.body.msg_id?;
+        let msg_type = match self.apply(&request.body.msg_type) {
+            Ok(reply) => serde_json::to_value(reply),
+            Err(error) => serde_json::to_value(error),
+        }
+        .expect("kv replies serialize");
+        Some(
+            serde_json::to_value(Message {
+                src: request.dest.clone(),
+                dest: request.src.clone(),
+                body: Body {
+                    msg_id: None,
+                    in_reply_to: Some(in_reply_to),
+                    msg_type,
+                },
+            })
+            .expect("kv replies serialize"),
+        )
+    }
+}
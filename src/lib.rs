@@ -1,54 +1,95 @@
+pub mod checker;
+pub mod crdt;
+pub mod dispatcher;
 pub mod gossip;
+pub mod history;
 pub mod init_state;
+pub mod kv;
 pub mod log;
+pub mod membership;
 pub mod message;
+pub mod node;
+pub mod plumtree;
+pub mod protocol;
+#[cfg(feature = "rand")]
+pub mod random;
 pub mod sender;
+#[cfg(feature = "sim")]
+pub mod sim;
 pub mod stdout_writer;
+pub mod topology;
 pub mod traits;
 
-use anyhow::Result;
-use message::Message;
+pub use node::Node;
+
+use anyhow::{bail, Result};
+use message::{ErrorCode, MaelstromError, Message};
+use protocol::Inbound;
 use serde::de::DeserializeOwned;
 use serde_json::Value;
-use std::sync::{Arc, Mutex};
-use tokio::sync::broadcast::{error::RecvError, Receiver, Sender};
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, BufReader},
+    sync::mpsc::{Receiver, Sender},
+};
 
-struct Wrapper<T: Iterator> {
-    input: T,
+/// What could not be read from the input
+#[derive(Default, Debug)]
+pub struct InputStats {
+    /// Lines that are not JSON
+    pub malformed: AtomicUsize,
 }
 
-impl<T: Iterator> Wrapper<T> {
-    fn next(&mut self) -> Option<<T as Iterator>::Item> {
-        self.input.next()
-    }
-}
-
-unsafe impl<T: Iterator> Send for Wrapper<T> {}
-
-/// # Panics
-///
-/// - if inner locks become poisoned
-pub fn init_stdin(tx: Sender<Value>) {
-    tokio::task::spawn_blocking(move || {
-        let stdin = std::io::stdin().lock();
-        let inputs = Arc::new(Mutex::new(Wrapper {
-            input: serde_json::Deserializer::from_reader(stdin).into_iter::<Value>(),
-        }));
-
-        loop {
-            let input = match inputs.lock().unwrap().next() {
-                Some(Ok(input)) => input,
-                None => {
-                    break;
-                }
-                _ => continue,
-            };
-
-            if tx.send(input).is_err() {
+/// Feeds `tx` with every line of `input`, parsed as JSON, until `input`
+/// ends. `tx` is dropped then, which closes the node's queues and shuts it
+/// down.
+pub async fn read_messages<R>(input: R, tx: Sender<Value>, stats: Arc<InputStats>)
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut lines = input.lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(error) => {
+                tracing::error!(%error, "Could not read input");
                 break;
             }
+        };
+        if line.trim().is_empty() {
+            continue;
         }
-    });
+        let value = match serde_json::from_str(&line) {
+            Ok(value) => value,
+            Err(error) => {
+                let malformed = stats.malformed.fetch_add(1, Ordering::Relaxed) + 1;
+                tracing::error!(%error, line, malformed, "Malformed input");
+                continue;
+            }
+        };
+        if tx.send(value).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Spawns a task feeding `tx` with the messages read from stdin, see
+/// [`read_messages`]
+pub fn init_stdin(tx: Sender<Value>) -> Arc<InputStats> {
+    let stats = Arc::new(InputStats::default());
+    tokio::spawn(read_messages(
+        BufReader::new(tokio::io::stdin()),
+        tx,
+        stats.clone(),
+    ));
+    stats
 }
 
 /// # Errors
@@ -59,22 +100,72 @@ where
     T: DeserializeOwned,
     F: Fn(Message<T>) -> Result<()>,
 {
-    loop {
-        match rx.recv().await {
-            Ok(value) => {
-                let input: Message<T> = match serde_json::from_value(value) {
-                    Ok(msg) => msg,
-                    Err(_) => {
-                        continue;
-                    }
-                };
-
-                callable(input)?;
+    while let Some(value) = rx.recv().await {
+        let input: Message<T> = match serde_json::from_value(value) {
+            Ok(msg) => msg,
+            Err(error) => {
+                tracing::warn!(%error, "Could not parse message");
+                continue;
             }
-            Err(RecvError::Closed) => {
-                break Err(RecvError::Closed.into());
+        };
+
+        callable(input)?;
+    }
+    bail!("Channel closed")
+}
+
+/// Feeds every request from `rx` to `callable`. When `callable` fails the
+/// request is answered with an `error` reply instead of stopping: errors that
+/// are not a [`MaelstromError`] are reported as an indefinite `crash`.
+/// Requests of an unknown type are answered with `not-supported`, and the
+/// ones that fail to parse with `malformed-request`.
+///
+/// # Panics
+///
+/// - if locks are poisoned
+///
+/// # Errors
+///
+/// - When stream is closed
+pub async fn wait_for_request_then<T, F, W>(
+    rx: &mut Receiver<Value>,
+    output: &Arc<Mutex<sender::Sender<W>>>,
+    callable: F,
+) -> Result<()>
+where
+    T: Inbound,
+    F: Fn(&Message<T>) -> Result<()>,
+    W: Write,
+{
+    while let Some(value) = rx.recv().await {
+        let result = match serde_json::from_value::<Message<T>>(value.clone()) {
+            Ok(msg) => callable(&msg),
+            Err(error) => {
+                let msg_type = value["body"]["type"].as_str().unwrap_or_default();
+                if T::TYPES.contains(&msg_type) {
+                    Err(MaelstromError::new(ErrorCode::MalformedRequest, error.to_string()).into())
+                } else {
+                    Err(MaelstromError::new(
+                        ErrorCode::NotSupported,
+                        format!("unsupported message type {msg_type:?}"),
+                    )
+                    .into())
+                }
             }
-            Err(_) => continue,
+        };
+
+        let Err(error) = result else {
+            continue;
+        };
+        let error = MaelstromError::from(error);
+        let Ok(msg) = serde_json::from_value::<Message<Value>>(value) else {
+            tracing::warn!(%error, "Dropping unparseable message");
+            continue;
+        };
+        tracing::warn!(src = msg.src, %error, "Request failed");
+        if let Err(error) = output.lock().unwrap().reply_error(&msg, error) {
+            tracing::warn!(?error, "Could not reply with error");
         }
     }
+    bail!("Channel closed")
 }
@@ -1,44 +1,122 @@
+use crate::crdt::Crdt;
 use crate::init_state::{Init, Initable};
-use std::collections::{hash_map::Values, HashMap};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
 
+/// How many of the first values inserted by each node a [`Log`] holds
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct VersionVector(BTreeMap<String, usize>);
+
+impl VersionVector {
+    #[must_use]
+    pub fn get(&self, node: &str) -> usize {
+        self.0.get(node).copied().unwrap_or_default()
+    }
+
+    /// Keeps the highest count of each node
+    pub fn merge(&mut self, other: &Self) {
+        for (node, count) in &other.0 {
+            let current = self.0.entry(node.clone()).or_default();
+            *current = (*current).max(*count);
+        }
+    }
+}
+
+/// Values of a [`Log`] grouped by the node that inserted them, each with its
+/// position among that node's values. Not a map of positions: integer keys
+/// do not survive the flattened, tagged Maelstrom bodies.
+pub type Delta<T> = BTreeMap<String, Vec<(usize, T)>>;
+
+/// Values inserted by every node. Each node numbers its own values, so the
+/// log can tell which ones another log lacks from its [`VersionVector`].
+///
+/// As a [`Crdt`], a log is the union of the values of its replicas, and is
+/// sent over the wire as its [`Delta`].
 #[derive(Clone, Debug)]
 pub struct Log<T> {
     node: String,
-    counter: usize,
-    values: HashMap<String, T>,
+    values: BTreeMap<String, BTreeMap<usize, T>>,
+    version: VersionVector,
 }
 
 impl<T: Clone> Log<T> {
+    /// Inserts the `counter`-th value of `origin`, returns it if it was new
     #[must_use]
-    pub(crate) fn insert_with_key<'b>(&mut self, key: &str, val: &'b T) -> Option<&'b T> {
-        let inserted = !self.values.contains_key(key);
-        self.values.insert(key.to_string(), val.clone());
-        if inserted {
-            Some(val)
-        } else {
-            None
+    pub(crate) fn insert_from<'b>(
+        &mut self,
+        origin: &str,
+        counter: usize,
+        val: &'b T,
+    ) -> Option<&'b T> {
+        let values = self.values.entry(origin.to_string()).or_default();
+        if values.insert(counter, val.clone()).is_some() {
+            return None;
+        }
+        let mut count = self.version.get(origin);
+        while values.contains_key(&count) {
+            count += 1;
         }
+        self.version.0.insert(origin.to_string(), count);
+        Some(val)
     }
 
     #[must_use]
     pub fn insert<'b>(&mut self, val: &'b T) -> Option<&'b T> {
-        let inserted = self.insert_with_key(&format!("{}-{}", self.node, self.counter), val);
-        self.counter += 1;
-        inserted
+        let node = self.node.clone();
+        let counter = self.version.get(&node);
+        self.insert_from(&node, counter, val)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.values.values().flat_map(BTreeMap::values)
     }
 
     #[must_use]
-    pub fn values(&self) -> Values<String, T> {
-        self.values.values()
+    pub fn version(&self) -> &VersionVector {
+        &self.version
     }
-}
 
-impl<T> IntoIterator for Log<T> {
-    type Item = <HashMap<String, T> as IntoIterator>::Item;
-    type IntoIter = <HashMap<String, T> as IntoIterator>::IntoIter;
+    /// The values a log at `version` is missing
+    #[must_use]
+    pub fn since(&self, version: &VersionVector) -> Delta<T> {
+        self.values
+            .keys()
+            .map(|origin| {
+                (
+                    origin.clone(),
+                    self.values_from(origin, version.get(origin)),
+                )
+            })
+            .filter(|(_, missing)| !missing.is_empty())
+            .collect()
+    }
+
+    /// Inserts every value of `other`, returns the ones that were new
+    pub(crate) fn merge_from(&mut self, other: &Self) -> Vec<T> {
+        let mut new = Vec::new();
+        for (origin, values) in &other.values {
+            for (counter, val) in values {
+                if let Some(val) = self.insert_from(origin, *counter, val) {
+                    new.push(val.clone());
+                }
+            }
+        }
+        new
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.values.into_iter()
+    /// The values `origin` inserted, from its `counter`-th one
+    #[must_use]
+    pub fn values_from(&self, origin: &str, counter: usize) -> Vec<(usize, T)> {
+        self.values
+            .get(origin)
+            .map(|values| {
+                values
+                    .range(counter..)
+                    .map(|(counter, val)| (*counter, val.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 }
 
@@ -46,8 +124,81 @@ impl<T> Initable for Log<T> {
     fn with_init(init: Init) -> Self {
         Self {
             node: init.node_id,
-            counter: 0,
-            values: HashMap::new(),
+            values: BTreeMap::new(),
+            version: VersionVector::default(),
+        }
+    }
+}
+
+impl<T> Default for Log<T> {
+    fn default() -> Self {
+        Self {
+            node: String::new(),
+            values: BTreeMap::new(),
+            version: VersionVector::default(),
+        }
+    }
+}
+
+/// Logs are equal when they hold the same values, whichever node holds them
+impl<T: PartialEq> PartialEq for Log<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+
+impl<T: Clone> From<Delta<T>> for Log<T> {
+    fn from(delta: Delta<T>) -> Self {
+        let mut log = Self::default();
+        for (origin, values) in &delta {
+            for (counter, val) in values {
+                let _ = log.insert_from(origin, *counter, val);
+            }
         }
+        log
+    }
+}
+
+impl<T: Clone + Serialize> Serialize for Log<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.since(&VersionVector::default()).serialize(serializer)
+    }
+}
+
+impl<'de, T: Clone + Deserialize<'de>> Deserialize<'de> for Log<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Delta::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// Summarized by its [`VersionVector`]
+impl<T: Clone + PartialEq + Serialize + DeserializeOwned> Crdt for Log<T> {
+    type Value = Vec<T>;
+    type Summary = VersionVector;
+
+    fn merge(&mut self, other: &Self) {
+        self.merge_from(other);
+    }
+
+    /// Counts the values that follow on from those `summary` already counts
+    fn summarize_into(&self, summary: &mut VersionVector) {
+        for (origin, values) in &self.values {
+            let count = summary.0.entry(origin.clone()).or_default();
+            while values.contains_key(count) {
+                *count += 1;
+            }
+        }
+    }
+
+    fn delta(&self, since: &VersionVector) -> Self {
+        self.since(since).into()
+    }
+
+    fn value(&self) -> Vec<T> {
+        self.values().cloned().collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.values.is_empty()
     }
 }
@@ -0,0 +1,503 @@
+//! SWIM failure detection and membership.
+//!
+//! Every period [`handle`] pings one node, going round the cluster. A node
+//! that does not answer in time is pinged again through `indirect_probes`
+//! others (`ping_req`), and suspected if none of them gets an answer either.
+//! A suspected node that does not refute the suspicion within
+//! `suspicion_timeout` is declared dead. What a node learns is piggybacked on
+//! the pings and their replies, so it spreads without extra messages.
+//!
+//! Each node numbers its own lives: hearing that it is suspected or dead, it
+//! starts a new incarnation and spreads that it is alive, which overrides any
+//! older news about it. Dead nodes are still pinged, so that a node cut off by
+//! a partition is taken back once it heals.
+
+use crate::init_state::{Init, InitState, Initable};
+use crate::message::Message;
+use crate::sender::Sender;
+use crate::wait_for_message_then;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc::Receiver},
+    task::JoinSet,
+    time::Instant,
+};
+
+/// Message types handled by [`handle`]
+pub const MESSAGE_TYPES: &[&str] = &["ping", "ping_ok", "ping_req", "ping_req_ok"];
+
+/// Each update is piggybacked `RETRANSMISSIONS * log2(nodes)` times
+const RETRANSMISSIONS: usize = 3;
+
+/// Capacity of the change notification channel
+const CHANGES_CAPACITY: usize = 64;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Alive,
+    Suspected,
+    Dead,
+}
+
+/// The status of `node` during its `incarnation`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Update {
+    pub node: String,
+    pub status: Status,
+    pub incarnation: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum SwimMessages {
+    Ping {
+        updates: Vec<Update>,
+    },
+    PingOk {
+        updates: Vec<Update>,
+    },
+    /// Asks the receiver to ping `target` and answer if it did
+    PingReq {
+        target: String,
+        updates: Vec<Update>,
+    },
+    PingReqOk {
+        updates: Vec<Update>,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Every how often a node is pinged
+    pub period: Duration,
+    /// How long to wait for the direct ping, the indirect ones have the rest
+    /// of the period
+    pub ping_timeout: Duration,
+    /// How many nodes are asked to ping a node that does not answer
+    pub indirect_probes: usize,
+    /// How long a node may stay suspected before it is declared dead
+    pub suspicion_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            period: Duration::from_millis(500),
+            ping_timeout: Duration::from_millis(150),
+            indirect_probes: 3,
+            suspicion_timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+struct Member {
+    status: Status,
+    incarnation: usize,
+    since: Instant,
+}
+
+/// What a node knows of the others. Until [`handle`] runs, every node is
+/// alive.
+pub struct Membership {
+    node: String,
+    incarnation: usize,
+    members: BTreeMap<String, Member>,
+    /// Updates to piggyback, and how many more times
+    pending: BTreeMap<String, (Update, usize)>,
+    retransmissions: usize,
+    next_probe: usize,
+    changes: broadcast::Sender<Update>,
+}
+
+impl Membership {
+    #[must_use]
+    pub fn new(init: &Init) -> Self {
+        let now = Instant::now();
+        let members = init
+            .node_ids
+            .iter()
+            .filter(|node| **node != init.node_id)
+            .map(|node| {
+                let member = Member {
+                    status: Status::Alive,
+                    incarnation: 0,
+                    since: now,
+                };
+                (node.clone(), member)
+            })
+            .collect();
+        let nodes = init.node_ids.len().max(2);
+        Self {
+            node: init.node_id.clone(),
+            incarnation: 0,
+            members,
+            pending: BTreeMap::new(),
+            retransmissions: RETRANSMISSIONS * (usize::BITS - (nodes - 1).leading_zeros()) as usize,
+            // Nodes start probing different nodes
+            next_probe: init
+                .node_ids
+                .iter()
+                .position(|node| *node == init.node_id)
+                .unwrap_or_default(),
+            changes: broadcast::channel(CHANGES_CAPACITY).0,
+        }
+    }
+
+    fn with_status(&self, status: Status) -> BTreeSet<&str> {
+        self.members
+            .iter()
+            .filter(|(_, member)| member.status == status)
+            .map(|(node, _)| node.as_str())
+            .collect()
+    }
+
+    /// Nodes believed alive, this one included
+    #[must_use]
+    pub fn alive(&self) -> BTreeSet<&str> {
+        let mut alive = self.with_status(Status::Alive);
+        alive.insert(&self.node);
+        alive
+    }
+
+    #[must_use]
+    pub fn suspected(&self) -> BTreeSet<&str> {
+        self.with_status(Status::Suspected)
+    }
+
+    #[must_use]
+    pub fn dead(&self) -> BTreeSet<&str> {
+        self.with_status(Status::Dead)
+    }
+
+    /// `None` for nodes outside the cluster
+    #[must_use]
+    pub fn status(&self, node: &str) -> Option<Status> {
+        if node == self.node {
+            return Some(Status::Alive);
+        }
+        self.members.get(node).map(|member| member.status)
+    }
+
+    /// Receives every status change from now on
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<Update> {
+        self.changes.subscribe()
+    }
+
+    fn spread(&mut self, update: Update) {
+        self.pending
+            .insert(update.node.clone(), (update, self.retransmissions));
+    }
+
+    /// Applies `update` if it is newer than what is known of its node
+    fn apply(&mut self, update: &Update) {
+        if update.node == self.node {
+            if update.status != Status::Alive && update.incarnation >= self.incarnation {
+                self.incarnation = update.incarnation + 1;
+                tracing::debug!(
+                    incarnation = self.incarnation,
+                    "Refuting {:?}",
+                    update.status
+                );
+                self.spread(Update {
+                    node: self.node.clone(),
+                    status: Status::Alive,
+                    incarnation: self.incarnation,
+                });
+            }
+            return;
+        }
+
+        let Some(member) = self.members.get_mut(&update.node) else {
+            return;
+        };
+        let newer = match (update.status, member.status) {
+            (Status::Alive, _)
+            | (Status::Suspected, Status::Suspected)
+            | (Status::Suspected | Status::Dead, Status::Dead) => {
+                update.incarnation > member.incarnation
+            }
+            (Status::Suspected, Status::Alive) | (Status::Dead, _) => {
+                update.incarnation >= member.incarnation
+            }
+        };
+        if !newer {
+            return;
+        }
+
+        let changed = member.status != update.status;
+        member.status = update.status;
+        member.incarnation = update.incarnation;
+        if changed {
+            member.since = Instant::now();
+            tracing::info!(node = update.node, status = ?update.status, "Membership changed");
+            let _ = self.changes.send(update.clone());
+        }
+        self.spread(update.clone());
+    }
+
+    fn apply_all(&mut self, updates: &[Update]) {
+        for update in updates {
+            self.apply(update);
+        }
+    }
+
+    /// Updates to piggyback on a message to `dest`, which always learns if it
+    /// is not believed alive, so that it can refute it
+    fn updates_for(&mut self, dest: &str) -> Vec<Update> {
+        let mut updates: Vec<_> = self
+            .pending
+            .values()
+            .map(|(update, _)| update.clone())
+            .collect();
+        self.pending.retain(|_, (_, left)| {
+            *left -= 1;
+            *left > 0
+        });
+        if let Some(member) = self.members.get(dest) {
+            if member.status != Status::Alive && !updates.iter().any(|update| update.node == dest) {
+                updates.push(Update {
+                    node: dest.to_string(),
+                    status: member.status,
+                    incarnation: member.incarnation,
+                });
+            }
+        }
+        updates
+    }
+
+    fn next_target(&mut self) -> Option<String> {
+        let target = self
+            .members
+            .keys()
+            .nth(self.next_probe % self.members.len().max(1))
+            .cloned();
+        self.next_probe += 1;
+        target
+    }
+
+    /// Up to `count` alive nodes to ping `target` on our behalf
+    fn indirect_peers(&self, target: &str, count: usize) -> Vec<String> {
+        let (before, after): (Vec<_>, Vec<_>) = self
+            .members
+            .iter()
+            .filter(|(node, member)| *node != target && member.status == Status::Alive)
+            .map(|(node, _)| node.clone())
+            .partition(|node| node.as_str() < target);
+        after.into_iter().chain(before).take(count).collect()
+    }
+
+    fn suspect(&mut self, node: &str) {
+        let Some(member) = self.members.get(node) else {
+            return;
+        };
+        if member.status == Status::Alive {
+            let update = Update {
+                node: node.to_string(),
+                status: Status::Suspected,
+                incarnation: member.incarnation,
+            };
+            self.apply(&update);
+        }
+    }
+
+    /// Declares dead the nodes suspected for longer than `timeout`
+    fn expire(&mut self, timeout: Duration) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .members
+            .iter()
+            .filter(|(_, member)| {
+                member.status == Status::Suspected && now >= member.since + timeout
+            })
+            .map(|(node, member)| Update {
+                node: node.clone(),
+                status: Status::Dead,
+                incarnation: member.incarnation,
+            })
+            .collect();
+        for update in &expired {
+            self.apply(update);
+        }
+    }
+}
+
+fn reply_updates(reply: &Message<SwimMessages>) -> &[Update] {
+    match reply.body.msg_type {
+        SwimMessages::Ping { ref updates }
+        | SwimMessages::PingOk { ref updates }
+        | SwimMessages::PingReq { ref updates, .. }
+        | SwimMessages::PingReqOk { ref updates } => updates,
+    }
+}
+
+/// Pings `target` and applies what its reply carries
+async fn ping<StateImpl, W>(
+    target: &str,
+    output: &Arc<Mutex<Sender<W>>>,
+    state: &Arc<Mutex<InitState<StateImpl>>>,
+    timeout: Duration,
+) -> Result<()>
+where
+    W: Write,
+    StateImpl: Initable,
+{
+    let updates = state.lock().unwrap().membership_mut().updates_for(target);
+    let reply = output
+        .lock()
+        .unwrap()
+        .rpc_timeout(target, SwimMessages::Ping { updates }, timeout);
+    let reply: Message<SwimMessages> = reply.await?;
+    state
+        .lock()
+        .unwrap()
+        .membership_mut()
+        .apply_all(reply_updates(&reply));
+    Ok(())
+}
+
+/// Pings `target`, then through other nodes, and suspects it if no answer
+/// comes back within the period
+async fn probe<StateImpl, W>(
+    target: String,
+    output: Arc<Mutex<Sender<W>>>,
+    state: Arc<Mutex<InitState<StateImpl>>>,
+    config: Config,
+) where
+    W: Write + Send + 'static,
+    StateImpl: Initable + Send + 'static,
+{
+    if ping(&target, &output, &state, config.ping_timeout)
+        .await
+        .is_ok()
+    {
+        return;
+    }
+
+    let timeout = config.period.saturating_sub(config.ping_timeout);
+    let mut requests = JoinSet::new();
+    {
+        let mut state = state.lock().unwrap();
+        let membership = state.membership_mut();
+        for peer in membership.indirect_peers(&target, config.indirect_probes) {
+            let msg_type = SwimMessages::PingReq {
+                target: target.clone(),
+                updates: membership.updates_for(&peer),
+            };
+            let reply = output.lock().unwrap().rpc_timeout(&peer, msg_type, timeout);
+            requests.spawn(async move {
+                let reply: Message<SwimMessages> = reply.await?;
+                anyhow::Ok(reply)
+            });
+        }
+    }
+
+    while let Some(reply) = requests.join_next().await {
+        if let Ok(Ok(reply)) = reply {
+            state
+                .lock()
+                .unwrap()
+                .membership_mut()
+                .apply_all(reply_updates(&reply));
+            return;
+        }
+    }
+    tracing::debug!(target, "No answer, suspecting");
+    state.lock().unwrap().membership_mut().suspect(&target);
+}
+
+fn handle_msg<StateImpl, W>(
+    input: Message<SwimMessages>,
+    output: &Arc<Mutex<Sender<W>>>,
+    state: &Arc<Mutex<InitState<StateImpl>>>,
+    config: Config,
+) where
+    W: Write + Send + 'static,
+    StateImpl: Initable + Send + 'static,
+{
+    let mut state_guard = state.lock().unwrap();
+    let membership = state_guard.membership_mut();
+    membership.apply_all(reply_updates(&input));
+    match input.body.msg_type {
+        SwimMessages::Ping { .. } => {
+            let updates = membership.updates_for(&input.src);
+            let _ = output
+                .lock()
+                .unwrap()
+                .reply(&input, SwimMessages::PingOk { updates });
+        }
+        SwimMessages::PingReq { ref target, .. } => {
+            let target = target.clone();
+            std::mem::drop(state_guard);
+            let output = output.clone();
+            let state = state.clone();
+            tokio::spawn(async move {
+                if ping(&target, &output, &state, config.ping_timeout)
+                    .await
+                    .is_ok()
+                {
+                    let updates = state
+                        .lock()
+                        .unwrap()
+                        .membership_mut()
+                        .updates_for(&input.src);
+                    let _ = output
+                        .lock()
+                        .unwrap()
+                        .reply(&input, SwimMessages::PingReqOk { updates });
+                }
+            });
+        }
+        // Replies that arrived too late
+        SwimMessages::PingOk { .. } | SwimMessages::PingReqOk { .. } => {}
+    }
+}
+
+/// Runs SWIM with the other nodes, keeping [`InitState::membership`] up to
+/// date
+///
+/// # Panics
+///
+/// - if locks are poisoned
+pub async fn handle<StateImpl, W>(
+    mut rx: Receiver<Value>,
+    output: Arc<Mutex<Sender<W>>>,
+    state: Arc<Mutex<InitState<StateImpl>>>,
+    config: Config,
+) where
+    W: Write + Send + 'static,
+    StateImpl: Initable + Send + 'static,
+{
+    let mut interval = tokio::time::interval(config.period);
+    loop {
+        tokio::select! {
+            biased;
+            result = wait_for_message_then(&mut rx, |msg| {
+                handle_msg(msg, &output, &state, config);
+                Ok(())
+            }) => {
+                if result.is_err() {
+                    break;
+                }
+            }
+            _ = interval.tick() => {
+                let mut state_guard = state.lock().unwrap();
+                let membership = state_guard.membership_mut();
+                membership.expire(config.suspicion_timeout);
+                if let Some(target) = membership.next_target() {
+                    tokio::spawn(probe(target, output.clone(), state.clone(), config));
+                }
+            }
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Body<Type> {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub msg_id: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub in_reply_to: Option<usize>,
     #[serde(flatten)]
     pub msg_type: Type,
@@ -14,3 +17,114 @@ pub struct Message<Type> {
     pub dest: String,
     pub body: Body<Type>,
 }
+
+/// Error codes defined by the Maelstrom protocol
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(from = "usize", into = "usize")]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    Other(usize),
+}
+
+impl ErrorCode {
+    /// Definite errors guarantee that the operation did not take place,
+    /// indefinite ones (timeouts, crashes, unknown codes) may or may not have
+    #[must_use]
+    pub fn is_definite(&self) -> bool {
+        !matches!(self, Self::Timeout | Self::Crash | Self::Other(_))
+    }
+}
+
+impl From<usize> for ErrorCode {
+    fn from(code: usize) -> Self {
+        match code {
+            0 => Self::Timeout,
+            1 => Self::NodeNotFound,
+            10 => Self::NotSupported,
+            11 => Self::TemporarilyUnavailable,
+            12 => Self::MalformedRequest,
+            13 => Self::Crash,
+            14 => Self::Abort,
+            20 => Self::KeyDoesNotExist,
+            21 => Self::KeyAlreadyExists,
+            22 => Self::PreconditionFailed,
+            30 => Self::TxnConflict,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<ErrorCode> for usize {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Other(other) => other,
+        }
+    }
+}
+
+/// Body of a Maelstrom `error` message
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename = "error")]
+pub struct MaelstromError {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+impl MaelstromError {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: text.into(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_definite(&self) -> bool {
+        self.code.is_definite()
+    }
+}
+
+impl Display for MaelstromError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} ({}): {}",
+            self.code,
+            usize::from(self.code),
+            self.text
+        )
+    }
+}
+
+impl std::error::Error for MaelstromError {}
+
+/// Errors that are not already a [`MaelstromError`] are reported as a
+/// `crash`, since we cannot tell whether the operation took effect
+impl From<anyhow::Error> for MaelstromError {
+    fn from(error: anyhow::Error) -> Self {
+        error
+            .downcast::<Self>()
+            .unwrap_or_else(|error| Self::new(ErrorCode::Crash, format!("{error:#}")))
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{
+    crdt::Crdt,
+    dispatcher::{Dispatcher, QUEUE_CAPACITY},
+    gossip,
+    init_state::{init_parser, InitState, Initable},
+    membership,
+    message::Message,
+    plumtree,
+    protocol::Inbound,
+    sender::{Batching, Sender},
+    stdout_writer::StdOutWriter,
+    traits::{replica::Replica, store::Store},
+    wait_for_request_then,
+};
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    future::Future,
+    io::Write,
+    pin::Pin,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc::{self, Receiver},
+    time::Instant,
+};
+use tracing_subscriber::{fmt, prelude::*};
+
+type Output<W> = Arc<Mutex<Sender<W>>>;
+type State<StateImpl> = Arc<Mutex<InitState<StateImpl>>>;
+type Service<StateImpl, W> = Box<
+    dyn FnOnce(
+            Receiver<Value>,
+            Output<W>,
+            State<StateImpl>,
+        ) -> Pin<Box<dyn Future<Output = ()> + Send>>
+        + Send,
+>;
+
+/// Runs a Maelstrom node: answers `init`, starts the background services and
+/// feeds every request to a single handler.
+pub struct Node<StateImpl: Initable, W: Write = StdOutWriter> {
+    services: Vec<(&'static [&'static str], Service<StateImpl, W>)>,
+    batching: Option<Batching>,
+}
+
+impl<StateImpl: Initable, W: Write> Default for Node<StateImpl, W> {
+    fn default() -> Self {
+        Self {
+            services: Vec::new(),
+            batching: None,
+        }
+    }
+}
+
+impl<StateImpl, W> Node<StateImpl, W>
+where
+    StateImpl: Initable + Send + 'static,
+    W: Write + Send + 'static,
+{
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns `service` once the node is initialized. It receives every
+    /// message whose type is in `msg_types`, and should return once `rx` is
+    /// closed: the node shuts down when its input ends, after every service
+    /// did.
+    #[must_use]
+    pub fn with_service<F, Fut>(mut self, msg_types: &'static [&'static str], service: F) -> Self
+    where
+        F: FnOnce(Receiver<Value>, Output<W>, State<StateImpl>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.services.push((
+            msg_types,
+            Box::new(move |rx, output, state| Box::pin(service(rx, output, state))),
+        ));
+        self
+    }
+
+    /// Replicates the node's [`Replica`] to its neighbors with
+    /// [`gossip::handle`]
+    #[must_use]
+    pub fn with_gossip(self, periodicity: Duration) -> Self
+    where
+        StateImpl: Replica,
+        StateImpl::Crdt: Send + 'static,
+        <StateImpl::Crdt as Crdt>::Summary: Send,
+    {
+        self.with_service(gossip::MESSAGE_TYPES, move |rx, output, state| {
+            gossip::handle(rx, output, state, periodicity)
+        })
+    }
+
+    /// Replicates the node's [`Store`] to its neighbors with
+    /// [`plumtree::handle`], an alternative to [`Node::with_gossip`]
+    #[must_use]
+    pub fn with_plumtree<T>(self, periodicity: Duration) -> Self
+    where
+        T: Clone + Serialize + Eq + DeserializeOwned + Send + 'static,
+        StateImpl: Store<T>,
+    {
+        self.with_service(plumtree::MESSAGE_TYPES, move |rx, output, state| {
+            plumtree::handle::<T, _, _>(rx, output, state, periodicity)
+        })
+    }
+
+    /// Detects failed nodes with [`membership::handle`]
+    #[must_use]
+    pub fn with_membership(self, config: membership::Config) -> Self {
+        self.with_service(membership::MESSAGE_TYPES, move |rx, output, state| {
+            membership::handle(rx, output, state, config)
+        })
+    }
+
+    /// Sends messages to other nodes in batches, see [`Sender::set_batching`]
+    #[must_use]
+    pub fn with_batching(mut self, batching: Batching) -> Self {
+        self.batching = Some(batching);
+        self
+    }
+
+    /// Calls `tick` every `period`
+    #[must_use]
+    pub fn with_timer<F>(self, period: Duration, tick: F) -> Self
+    where
+        F: Fn(&Output<W>, &State<StateImpl>) + Send + 'static,
+    {
+        self.with_service(&[], move |mut rx, output, state| async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                tokio::select! {
+                    _ = rx.recv() => break,
+                    _ = interval.tick() => tick(&output, &state),
+                }
+            }
+        })
+    }
+
+    /// Runs the node over `writer`, receiving messages from `input`. Returns
+    /// once `input` is closed and every service is done, with every batch
+    /// sent.
+    ///
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    ///
+    /// # Errors
+    ///
+    /// - Did not receive Init
+    pub async fn run_with<M, F>(self, writer: W, input: Receiver<Value>, handler: F) -> Result<()>
+    where
+        M: Inbound,
+        F: Fn(&Message<M>, &Output<W>, &State<StateImpl>) -> Result<()>,
+    {
+        let output = Arc::new(Mutex::new(Sender::new(writer)));
+
+        let mut dispatcher = Dispatcher::new(output.lock().unwrap().replies());
+        let init_rx = dispatcher.register(&["init"]);
+        let services: Vec<_> = self
+            .services
+            .into_iter()
+            .map(|(msg_types, service)| (service, dispatcher.register(msg_types)))
+            .collect();
+        let mut rx = dispatcher.register_fallback();
+        let stats = dispatcher.stats();
+        tokio::spawn(dispatcher.run(input));
+
+        let state = init_parser::<StateImpl, W>(init_rx, output.clone()).await?;
+        let flusher = self.batching.map(|batching| {
+            let init = state.lock().unwrap().get_init().clone();
+            let mut peers = init.node_ids;
+            peers.remove(&init.node_id);
+            output.lock().unwrap().set_batching(batching, peers);
+            tokio::spawn(flush_batches(output.clone(), batching.max_delay))
+        });
+        let services: Vec<_> = services
+            .into_iter()
+            .map(|(service, rx)| tokio::spawn(service(rx, output.clone(), state.clone())))
+            .collect();
+
+        let _ = wait_for_request_then(&mut rx, &output, |msg| handler(msg, &output, &state)).await;
+        tracing::info!(
+            lagged = stats.lagged.load(Ordering::Relaxed),
+            unroutable = stats.unroutable.load(Ordering::Relaxed),
+            dropped = stats.dropped.load(Ordering::Relaxed),
+            "Input closed, shutting down"
+        );
+        for service in services {
+            let _ = service.await;
+        }
+        if let Some(flusher) = flusher {
+            flusher.abort();
+            if let Err(error) = output.lock().unwrap().flush_all() {
+                tracing::warn!(?error, "Could not send batch");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends batches as they become due
+async fn flush_batches<W: Write>(output: Output<W>, max_delay: Duration) {
+    loop {
+        let due = output
+            .lock()
+            .unwrap()
+            .next_due()
+            .unwrap_or_else(|| Instant::now() + max_delay);
+        tokio::time::sleep_until(due).await;
+        if let Err(error) = output.lock().unwrap().flush_due() {
+            tracing::warn!(?error, "Could not send batch");
+        }
+    }
+}
+
+impl<StateImpl> Node<StateImpl>
+where
+    StateImpl: Initable + Send + 'static,
+{
+    /// Runs the node over stdin / stdout, tracing to stderr, until stdin is
+    /// closed
+    ///
+    /// # Errors
+    ///
+    /// - Did not receive Init
+    pub async fn run<M, F>(self, handler: F) -> Result<()>
+    where
+        M: Inbound,
+        F: Fn(&Message<M>, &Output<StdOutWriter>, &State<StateImpl>) -> Result<()>,
+    {
+        let layer = fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_thread_ids(true)
+            .with_ansi(false)
+            .pretty();
+        tracing_subscriber::registry().with(layer).init();
+        let (tx, input) = mpsc::channel(QUEUE_CAPACITY);
+        let stats = crate::init_stdin(tx);
+        let (writer, written) = StdOutWriter::spawn();
+        self.run_with(writer, input, handler).await?;
+        let _ = written.await;
+        tracing::info!(
+            malformed = stats.malformed.load(Ordering::Relaxed),
+            "Node stopped"
+        );
+        Ok(())
+    }
+}
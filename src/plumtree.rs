@@ -0,0 +1,324 @@
+//! Epidemic broadcast trees (Plumtree), an alternative to [`crate::gossip`]
+//! for replicating a [`Store`].
+//!
+//! New values are pushed to *eager* peers on the next tick, and only
+//! announced to *lazy* peers with the [`VersionVector`] of the log
+//! (`ihave`). Receiving a value twice means two paths lead here: the later
+//! sender is told to `prune` the link, which becomes lazy. The eager links
+//! that remain form a spanning tree. When an announcement is not followed by
+//! the values it announced, the announcer is asked to `graft` the link back
+//! and send what is missing, which heals the tree. Announcements also go to
+//! every peer from time to time, so that values lost to a partition are
+//! recovered once it heals.
+
+use crate::init_state::{InitState, Initable};
+use crate::log::{Delta, VersionVector};
+use crate::message::{Body, Message};
+use crate::sender::Sender;
+use crate::traits::store::Store;
+use crate::wait_for_message_then;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::mpsc::Receiver, time::Instant};
+
+/// Message types handled by [`handle`]
+pub const MESSAGE_TYPES: &[&str] = &["eager_push", "ihave", "graft", "prune"];
+
+/// Every how many periods lazy peers hear of new values
+const ANNOUNCE_EVERY: usize = 3;
+
+/// Every how many periods announcements go to eager peers too
+const ANNOUNCE_TO_ALL_EVERY: usize = 30;
+
+/// How many periods an announced value may take to arrive by eager push
+/// before it is grafted
+const GRAFT_AFTER: u32 = 2;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum PlumtreeMessages<T: Serialize + Clone + Eq> {
+    EagerPush {
+        delta: Delta<T>,
+    },
+    #[serde(rename = "ihave")]
+    IHave {
+        version: VersionVector,
+    },
+    /// Makes the link eager again, `version` is what the sender has
+    Graft {
+        version: VersionVector,
+    },
+    Prune,
+}
+
+struct PlumtreeState<T> {
+    periodicity: Duration,
+    eager: BTreeSet<String>,
+    lazy: BTreeSet<String>,
+    /// How many of its own values the node pushed
+    pushed: usize,
+    /// Values to push to each eager peer on the next tick
+    pending: BTreeMap<String, Delta<T>>,
+    /// Version last announced to lazy peers
+    announced: VersionVector,
+    /// What peers announced that we do not have yet, and since when
+    missing: BTreeMap<String, (VersionVector, Instant)>,
+    ticks: usize,
+}
+
+impl<T: Clone> PlumtreeState<T> {
+    fn make_eager(&mut self, peer: &str) {
+        self.lazy.remove(peer);
+        self.eager.insert(peer.to_string());
+    }
+
+    fn make_lazy(&mut self, peer: &str) {
+        self.eager.remove(peer);
+        self.pending.remove(peer);
+        self.lazy.insert(peer.to_string());
+    }
+
+    /// Queues `delta` for every eager peer but `except`
+    fn push(&mut self, delta: &Delta<T>, except: Option<&str>) {
+        for peer in &self.eager {
+            if Some(peer.as_str()) == except {
+                continue;
+            }
+            let pending = self.pending.entry(peer.clone()).or_default();
+            for (origin, values) in delta {
+                pending
+                    .entry(origin.clone())
+                    .or_default()
+                    .extend(values.iter().cloned());
+            }
+        }
+    }
+
+    /// Starts with every neighbor eager, and follows topology changes
+    fn update_peers(&mut self, neighbors: &BTreeSet<String>, node: &str) {
+        self.eager.retain(|peer| neighbors.contains(peer));
+        self.pending.retain(|peer, _| neighbors.contains(peer));
+        self.lazy.retain(|peer| neighbors.contains(peer));
+        for neighbor in neighbors {
+            if neighbor != node && !self.lazy.contains(neighbor) {
+                self.eager.insert(neighbor.clone());
+            }
+        }
+    }
+}
+
+/// Whether `version` has values `ours` lacks
+fn is_ahead(version: &VersionVector, ours: &VersionVector) -> bool {
+    let mut merged = ours.clone();
+    merged.merge(version);
+    merged != *ours
+}
+
+fn send<T: Serialize + Clone + Eq, W: Write>(
+    output: &Arc<Mutex<Sender<W>>>,
+    src: &str,
+    dest: &str,
+    msg_type: PlumtreeMessages<T>,
+) {
+    let _ = output.lock().unwrap().send(
+        Message {
+            src: src.to_string(),
+            dest: dest.to_string(),
+            body: Body {
+                msg_id: None,
+                in_reply_to: None,
+                msg_type,
+            },
+        },
+        true,
+    );
+}
+
+fn handle_msg<T, StoreImpl, W>(
+    input: &Message<PlumtreeMessages<T>>,
+    output: &Arc<Mutex<Sender<W>>>,
+    state: &Arc<Mutex<InitState<StoreImpl>>>,
+    plumtree: &Arc<Mutex<PlumtreeState<T>>>,
+) where
+    W: Write,
+    T: Clone + Serialize + Eq + DeserializeOwned + Send + 'static,
+    StoreImpl: Store<T> + Send + Initable + 'static,
+{
+    let peer = &input.src;
+    let node = &input.dest;
+    match input.body.msg_type {
+        PlumtreeMessages::EagerPush { ref delta } => {
+            let mut state = state.lock().unwrap();
+            let mut new = Delta::new();
+            for (origin, values) in delta {
+                for (counter, val) in values {
+                    if let Some(val) = state.insert_from(origin, *counter, val) {
+                        state.new_value(val);
+                        new.entry(origin.clone())
+                            .or_insert_with(Vec::new)
+                            .push((*counter, val.clone()));
+                    }
+                }
+            }
+            std::mem::drop(state);
+
+            let mut plumtree = plumtree.lock().unwrap();
+            if new.is_empty() {
+                plumtree.make_lazy(peer);
+                send::<T, _>(output, node, peer, PlumtreeMessages::Prune);
+                return;
+            }
+            plumtree.make_eager(peer);
+            plumtree.push(&new, Some(peer));
+        }
+        PlumtreeMessages::IHave { ref version } => {
+            let ours = state.lock().unwrap().version().clone();
+            let mut plumtree = plumtree.lock().unwrap();
+            if is_ahead(version, &ours) {
+                let since = plumtree
+                    .missing
+                    .get(peer)
+                    .map_or_else(Instant::now, |(_, since)| *since);
+                plumtree
+                    .missing
+                    .insert(peer.clone(), (version.clone(), since));
+            } else {
+                plumtree.missing.remove(peer);
+            }
+        }
+        PlumtreeMessages::Graft { ref version } => {
+            let delta = state.lock().unwrap().since(version);
+            plumtree.lock().unwrap().make_eager(peer);
+            if !delta.is_empty() {
+                send(output, node, peer, PlumtreeMessages::EagerPush { delta });
+            }
+        }
+        PlumtreeMessages::Prune => plumtree.lock().unwrap().make_lazy(peer),
+    }
+}
+
+fn tick<T, StoreImpl, W>(
+    output: &Arc<Mutex<Sender<W>>>,
+    state: &Arc<Mutex<InitState<StoreImpl>>>,
+    plumtree: &Arc<Mutex<PlumtreeState<T>>>,
+) where
+    W: Write,
+    T: Clone + Serialize + Eq + DeserializeOwned + Send + 'static,
+    StoreImpl: Store<T> + Send + Initable + 'static,
+{
+    let state = state.lock().unwrap();
+    let node = &state.get_init().node_id;
+    let version = state.version().clone();
+    let mut plumtree = plumtree.lock().unwrap();
+    plumtree.update_peers(state.get_neighbors(), node);
+    plumtree.ticks += 1;
+
+    // Values inserted locally since the last tick
+    let own = state.values_from(node, plumtree.pushed);
+    if let Some((last, _)) = own.last() {
+        plumtree.pushed = last + 1;
+        plumtree.push(&Delta::from([(node.clone(), own)]), None);
+    }
+    for (peer, delta) in std::mem::take(&mut plumtree.pending) {
+        send(output, node, &peer, PlumtreeMessages::EagerPush { delta });
+    }
+
+    let everyone = plumtree.ticks.is_multiple_of(ANNOUNCE_TO_ALL_EVERY);
+    if everyone || plumtree.ticks.is_multiple_of(ANNOUNCE_EVERY) && version != plumtree.announced {
+        let peers = if everyone {
+            plumtree.eager.union(&plumtree.lazy).cloned().collect()
+        } else {
+            plumtree.lazy.clone()
+        };
+        for peer in peers {
+            send::<T, _>(
+                output,
+                node,
+                &peer,
+                PlumtreeMessages::IHave {
+                    version: version.clone(),
+                },
+            );
+        }
+        plumtree.announced = version.clone();
+    }
+
+    // Announced values that eager pushes did not bring in time
+    let now = Instant::now();
+    let graft_after = plumtree.periodicity * GRAFT_AFTER;
+    let mut grafts = Vec::new();
+    plumtree.missing.retain(|peer, (announced, since)| {
+        if !is_ahead(announced, &version) {
+            return false;
+        }
+        if now >= *since + graft_after {
+            grafts.push(peer.clone());
+            *since = now;
+        }
+        true
+    });
+    for peer in grafts {
+        tracing::debug!(peer, "Grafting");
+        plumtree.make_eager(&peer);
+        send::<T, _>(
+            output,
+            node,
+            &peer,
+            PlumtreeMessages::Graft {
+                version: version.clone(),
+            },
+        );
+    }
+}
+
+/// Replicates the node's [`Store`] to its neighbors. Local values are pushed
+/// on the next tick, every `periodicity`.
+///
+/// # Panics
+///
+/// - if locks are poisoned
+pub async fn handle<T, StoreImpl, W>(
+    mut rx: Receiver<Value>,
+    output: Arc<Mutex<Sender<W>>>,
+    state: Arc<Mutex<InitState<StoreImpl>>>,
+    periodicity: Duration,
+) where
+    W: Write,
+    T: Clone + Serialize + Eq + DeserializeOwned + Send + 'static,
+    StoreImpl: Store<T> + Send + Initable + 'static,
+{
+    let plumtree = Arc::new(Mutex::new(PlumtreeState {
+        periodicity,
+        eager: BTreeSet::new(),
+        lazy: BTreeSet::new(),
+        pushed: 0,
+        pending: BTreeMap::new(),
+        announced: VersionVector::default(),
+        missing: BTreeMap::new(),
+        ticks: 0,
+    }));
+    let mut interval = tokio::time::interval(periodicity);
+    loop {
+        tokio::select! {
+            biased;
+            result = wait_for_message_then(&mut rx, |msg| {
+                handle_msg(&msg, &output, &state, &plumtree);
+                Ok(())
+            }) => {
+                if result.is_err() {
+                    break;
+                }
+            }
+            _ = interval.tick() => {
+                tick(&output, &state, &plumtree);
+            }
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use symmetrical_octo_potato_macros::MaelstromProtocol;
+
+/// A message enum whose requests are paired with their `_ok` responses,
+/// usually through `#[derive(MaelstromProtocol)]`
+pub trait Protocol: Serialize {
+    /// The same messages without the responses
+    type Request: Inbound;
+
+    /// Whether `self` is the response paired with `request`
+    fn answers(&self, request: &Self::Request) -> bool;
+}
+
+/// Messages a node accepts from its clients
+pub trait Inbound: DeserializeOwned {
+    /// Wire `type` of every message
+    const TYPES: &'static [&'static str];
+}
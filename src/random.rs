@@ -0,0 +1,21 @@
+//! Source of randomness for node logic and the simulator.
+//!
+//! Everything random should be drawn through [`with_rng`] so that a
+//! deterministic simulation, which runs every node on a single thread, can
+//! replay a run from its seed.
+
+use rand::{rngs::StdRng, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Restarts this thread's random sequence from `seed`
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+pub fn with_rng<R>(f: impl FnOnce(&mut StdRng) -> R) -> R {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
@@ -1,26 +1,91 @@
-use crate::message::{Body, Message};
+use crate::{
+    message::{Body, ErrorCode, MaelstromError, Message},
+    protocol::Protocol,
+};
 use anyhow::{bail, Context, Result};
-use serde::Serialize;
-use std::io::Write;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    future::Future,
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::oneshot, time::Instant};
+
+type PendingReplies = Arc<Mutex<HashMap<usize, oneshot::Sender<Value>>>>;
+
+/// Message type of the envelope carrying a batch, unpacked by the
+/// [`crate::dispatcher::Dispatcher`]
+pub const BATCH: &str = "batch";
+
+/// When messages queued for the same node leave together
+#[derive(Clone, Copy, Debug)]
+pub struct Batching {
+    /// Messages in a batch, which leaves as soon as it is full
+    pub max_size: usize,
+    /// How long the first message of a batch may wait for others
+    pub max_delay: Duration,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Envelope {
+    Batch { messages: Vec<Value> },
+}
+
+/// Messages waiting for the same node, and when they are due
+struct Queue {
+    due: Instant,
+    messages: Vec<Value>,
+}
 
 #[derive(Default)]
 pub struct Sender<W: Write> {
     id: usize,
+    node_id: String,
     writer: W,
+    pending: PendingReplies,
+    batching: Option<Batching>,
+    /// Nodes that unpack batches, messages to anyone else leave right away
+    peers: BTreeSet<String>,
+    queues: BTreeMap<String, Queue>,
 }
 
 impl<W: Write> Sender<W> {
+    #[must_use]
+    pub fn new(writer: W) -> Self {
+        Self {
+            id: 0,
+            node_id: String::new(),
+            writer,
+            pending: PendingReplies::default(),
+            batching: None,
+            peers: BTreeSet::new(),
+            queues: BTreeMap::new(),
+        }
+    }
+
+    /// Queues messages to `peers` to send them in batches. Queues are only
+    /// sent once full, or when due if something calls [`Sender::flush_due`].
+    pub fn set_batching(&mut self, batching: Batching, peers: BTreeSet<String>) {
+        self.batching = Some(batching);
+        self.peers = peers;
+    }
+
     /// # Errors
     ///
     /// - failed to `send()`
-    pub fn reply<T: Serialize>(&mut self, request: Message<T>, msg_type: T) -> Result<()> {
+    pub fn reply<R, T: Serialize>(&mut self, request: &Message<R>, msg_type: T) -> Result<()> {
         let Some(in_reply_to) = request.body.msg_id else {
             bail!("not possible to reply");
         };
         self.send(
             Message {
-                src: request.dest,
-                dest: request.src,
+                src: request.dest.clone(),
+                dest: request.src.clone(),
                 body: Body {
                     msg_id: None,
                     in_reply_to: Some(in_reply_to),
@@ -31,6 +96,45 @@ impl<W: Write> Sender<W> {
         )
     }
 
+    /// Replies to `request` with the response paired with it
+    ///
+    /// # Errors
+    ///
+    /// - `msg_type` does not answer `request`
+    /// - failed to `reply()`
+    pub fn reply_to<P: Protocol>(
+        &mut self,
+        request: &Message<P::Request>,
+        msg_type: P,
+    ) -> Result<()> {
+        if !msg_type.answers(&request.body.msg_type) {
+            bail!("reply does not answer the request");
+        }
+        self.reply(request, msg_type)
+    }
+
+    /// # Errors
+    ///
+    /// - request has no `msg_id`
+    /// - failed to `send()`
+    pub fn reply_error<T>(&mut self, request: &Message<T>, error: MaelstromError) -> Result<()> {
+        let Some(in_reply_to) = request.body.msg_id else {
+            bail!("not possible to reply");
+        };
+        self.send(
+            Message {
+                src: request.dest.clone(),
+                dest: request.src.clone(),
+                body: Body {
+                    msg_id: None,
+                    in_reply_to: Some(in_reply_to),
+                    msg_type: error,
+                },
+            },
+            true,
+        )
+    }
+
     /// # Errors
     ///
     /// - failed to serialize structure
@@ -39,14 +143,233 @@ impl<W: Write> Sender<W> {
         if include_id {
             message.body.msg_id = Some(self.id);
         }
-        serde_json::to_writer(&mut self.writer, &message).context("Failed to serialize / write")?;
-        self.writer.flush().context("Failed to flush")?;
-        self.id += 1;
+        match self.batching {
+            Some(batching) if self.peers.contains(&message.dest) => {
+                let value = serde_json::to_value(&message).context("Failed to serialize")?;
+                let queue = self
+                    .queues
+                    .entry(message.dest.clone())
+                    .or_insert_with(|| Queue {
+                        due: Instant::now() + batching.max_delay,
+                        messages: Vec::new(),
+                    });
+                queue.messages.push(value);
+                let full = queue.messages.len() >= batching.max_size;
+                self.id += 1;
+                if full {
+                    self.flush_to(&message.dest)?;
+                }
+            }
+            _ => {
+                self.write(&message)?;
+                self.id += 1;
+            }
+        }
         Ok(())
     }
 
+    /// Writes `message` on its own line
+    fn write<T: Serialize>(&mut self, message: &T) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, message).context("Failed to serialize / write")?;
+        self.writer.write_all(b"\n").context("Failed to write")?;
+        self.writer.flush().context("Failed to flush")
+    }
+
+    /// Sends the messages queued for `dest`, alone if there is only one
+    fn flush_to(&mut self, dest: &str) -> Result<()> {
+        let Some(mut queue) = self.queues.remove(dest) else {
+            return Ok(());
+        };
+        if queue.messages.len() == 1 {
+            return self.write(&queue.messages.remove(0));
+        }
+        self.write(&Message {
+            src: self.node_id.clone(),
+            dest: dest.to_string(),
+            body: Body {
+                msg_id: None,
+                in_reply_to: None,
+                msg_type: Envelope::Batch {
+                    messages: queue.messages,
+                },
+            },
+        })
+    }
+
+    /// When the oldest queued batch is due
+    #[must_use]
+    pub fn next_due(&self) -> Option<Instant> {
+        self.queues.values().map(|queue| queue.due).min()
+    }
+
+    /// Sends the batches that waited long enough
+    ///
+    /// # Errors
+    ///
+    /// - failed to `send()` a batch, the others are still sent
+    pub fn flush_due(&mut self) -> Result<()> {
+        let now = Instant::now();
+        self.flush_where(|queue| queue.due <= now)
+    }
+
+    /// Sends every batch right away
+    ///
+    /// # Errors
+    ///
+    /// - failed to `send()` a batch, the others are still sent
+    pub fn flush_all(&mut self) -> Result<()> {
+        self.flush_where(|_| true)
+    }
+
+    fn flush_where(&mut self, due: impl Fn(&Queue) -> bool) -> Result<()> {
+        let due: Vec<_> = self
+            .queues
+            .iter()
+            .filter(|(_, queue)| due(queue))
+            .map(|(dest, _)| dest.clone())
+            .collect();
+        let mut result = Ok(());
+        for dest in due {
+            result = result.and(self.flush_to(&dest));
+        }
+        result
+    }
+
+    /// Sends `msg_type` to `dest` and returns a future that resolves with the
+    /// message carrying the matching `in_reply_to`. An `error` reply resolves
+    /// to a [`MaelstromError`].
+    ///
+    /// The request is written before this returns, so the future does not
+    /// borrow the sender. Dropping the future cancels the call: a reply that
+    /// arrives afterwards is no longer routed to it. Replies are only
+    /// delivered when something hands them to [`Replies::resolve`], as the
+    /// [`crate::dispatcher::Dispatcher`] does.
+    ///
+    /// # Panics
+    ///
+    /// - if inner locks become poisoned
+    pub fn rpc<T, R>(
+        &mut self,
+        dest: &str,
+        msg_type: T,
+    ) -> impl Future<Output = Result<Message<R>>> + Send + 'static
+    where
+        T: Serialize,
+        R: DeserializeOwned,
+    {
+        let id = self.id;
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        let guard = PendingGuard {
+            id,
+            pending: self.pending.clone(),
+        };
+
+        let sent = self
+            .send(
+                Message {
+                    src: self.node_id.clone(),
+                    dest: dest.to_string(),
+                    body: Body {
+                        msg_id: None,
+                        in_reply_to: None,
+                        msg_type,
+                    },
+                },
+                true,
+            )
+            .map(|()| (rx, guard));
+
+        async move {
+            let (rx, _guard) = sent?;
+            let reply = rx.await.context("Rpc cancelled")?;
+            if reply["body"]["type"] == "error" {
+                let error = serde_json::from_value::<Message<MaelstromError>>(reply)
+                    .context("Parsing rpc error")?;
+                return Err(error.body.msg_type.into());
+            }
+            serde_json::from_value(reply).context("Parsing rpc reply")
+        }
+    }
+
+    /// Same as [`Sender::rpc`] but fails with an indefinite
+    /// [`ErrorCode::Timeout`] once `timeout` elapses without a reply.
+    ///
+    /// # Panics
+    ///
+    /// - if inner locks become poisoned
+    pub fn rpc_timeout<T, R>(
+        &mut self,
+        dest: &str,
+        msg_type: T,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Message<R>>> + Send + 'static
+    where
+        T: Serialize,
+        R: DeserializeOwned,
+    {
+        let reply = self.rpc(dest, msg_type);
+        let dest = dest.to_string();
+        async move {
+            tokio::time::timeout(timeout, reply).await.map_err(|_| {
+                MaelstromError::new(ErrorCode::Timeout, format!("Rpc to {dest} timed out"))
+            })?
+        }
+    }
+
+    #[must_use]
+    pub fn replies(&self) -> Replies {
+        Replies {
+            pending: self.pending.clone(),
+        }
+    }
+
+    pub fn set_node_id(&mut self, node_id: &str) {
+        self.node_id = node_id.to_string();
+    }
+
     #[must_use]
     pub fn get_id(&self) -> usize {
         self.id
     }
 }
+
+struct PendingGuard {
+    id: usize,
+    pending: PendingReplies,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+/// Routes incoming replies to the `rpc` calls waiting on them
+#[derive(Clone)]
+pub struct Replies {
+    pending: PendingReplies,
+}
+
+impl Replies {
+    /// Hands `value` to the `rpc` call waiting on its `in_reply_to`.
+    /// Gives the message back if no call is waiting on it.
+    ///
+    /// # Panics
+    ///
+    /// - if inner locks become poisoned
+    pub fn resolve(&self, value: Value) -> Option<Value> {
+        let Some(in_reply_to) = value["body"]["in_reply_to"].as_u64() else {
+            return Some(value);
+        };
+        let waiting = usize::try_from(in_reply_to)
+            .ok()
+            .and_then(|id| self.pending.lock().unwrap().remove(&id));
+        match waiting {
+            Some(tx) => tx.send(value).err(),
+            None => Some(value),
+        }
+    }
+}
@@ -0,0 +1,566 @@
+//! In-process network of Maelstrom nodes, for tests.
+//!
+//! Every node runs its usual [`crate::Node`] event loop, but writes into a
+//! [`SimWriter`] instead of stdout. A router delivers what they write to the
+//! other nodes, to [`Client`]s or to the built-in KV services (`seq-kv`,
+//! `lin-kv` and `lww-kv`, backed by [`LocalKv`]), applying the faults set in
+//! [`NetworkConfig`] and the current partitions on the way. Nodes can also
+//! be separate programs, see [`run_process`].
+//!
+//! Within [`deterministic`], time is virtual and every random choice comes
+//! from [`crate::random`], so a run only depends on its seed: the same seed
+//! delivers the same messages in the same order at the same instants.
+
+use crate::{
+    kv::{KvMessage, LocalKv},
+    message::Message,
+    random,
+    sender::{Replies, Sender},
+};
+use anyhow::{bail, Context, Result};
+use rand::Rng;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    future::Future,
+    io::Write,
+    path::PathBuf,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::Command,
+    sync::mpsc::{self, Receiver, UnboundedReceiver, UnboundedSender},
+    time::Instant,
+};
+
+const KV_SERVICES: &[&str] = &["seq-kv", "lin-kv", "lww-kv"];
+
+#[derive(Clone, Debug)]
+pub struct NetworkConfig {
+    /// Every message is delayed by a duration picked uniformly in this range
+    pub latency: (Duration, Duration),
+    /// Probability for a message between two nodes to be lost
+    pub drop_rate: f64,
+    /// Probability for a message between two nodes to be delivered twice
+    pub duplicate_rate: f64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            latency: (Duration::ZERO, Duration::from_millis(1)),
+            drop_rate: 0.0,
+            duplicate_rate: 0.0,
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct NetworkStats {
+    /// Messages written by nodes to other nodes
+    pub between_nodes: AtomicUsize,
+    pub dropped: AtomicUsize,
+    pub duplicated: AtomicUsize,
+}
+
+/// A message handed to its destination
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delivery {
+    /// Time since the network was created
+    pub at: Duration,
+    pub message: Value,
+}
+
+/// [`Write`] sink handing every flushed message to the simulated network
+pub struct SimWriter {
+    buffer: Vec<u8>,
+    frames: UnboundedSender<Vec<u8>>,
+}
+
+impl Write for SimWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.frames
+            .send(std::mem::take(&mut self.buffer))
+            .map_err(|_| std::io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+struct NetworkState {
+    config: NetworkConfig,
+    start: Instant,
+    trace: Option<Vec<Delivery>>,
+    // node -> partition it belongs to, everyone can talk when empty
+    partitions: HashMap<String, usize>,
+    /// Inbox of every node, each emptied into its input by a task of its own
+    nodes: HashMap<String, UnboundedSender<Value>>,
+    clients: HashMap<String, Replies>,
+    kv: HashMap<String, LocalKv>,
+}
+
+struct Network {
+    state: Mutex<NetworkState>,
+    stats: NetworkStats,
+    frames: UnboundedSender<Vec<u8>>,
+}
+
+pub struct Sim {
+    network: Arc<Network>,
+    node_ids: BTreeSet<String>,
+    clients: usize,
+}
+
+impl Sim {
+    /// # Panics
+    ///
+    /// - if not called within a tokio runtime
+    #[must_use]
+    pub fn new(config: NetworkConfig) -> Self {
+        let (frames, rx) = mpsc::unbounded_channel();
+        let network = Arc::new(Network {
+            state: Mutex::new(NetworkState {
+                config,
+                start: Instant::now(),
+                trace: None,
+                partitions: HashMap::new(),
+                nodes: HashMap::new(),
+                clients: HashMap::new(),
+                kv: KV_SERVICES
+                    .iter()
+                    .map(|service| ((*service).to_string(), LocalKv::default()))
+                    .collect(),
+            }),
+            stats: NetworkStats::default(),
+            frames,
+        });
+        tokio::spawn(route(network.clone(), rx));
+        Self {
+            network,
+            node_ids: BTreeSet::new(),
+            clients: 0,
+        }
+    }
+
+    /// Starts `count` nodes named `n1`, `n2`, ... `node` is expected to run a
+    /// [`crate::Node`] over the given writer and input, e.g. with
+    /// `|writer, input| Node::<State, _>::new().run_with(writer, input, handler)`
+    ///
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    pub fn add_nodes<F, Fut>(&mut self, count: usize, node: F)
+    where
+        F: Fn(SimWriter, Receiver<Value>) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        for _ in 0..count {
+            let id = format!("n{}", self.node_ids.len() + 1);
+            let (tx, rx) = mpsc::channel(crate::dispatcher::QUEUE_CAPACITY);
+            // A node that falls behind only delays its own messages
+            let (inbox, mut pending) = mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                while let Some(value) = pending.recv().await {
+                    if tx.send(value).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            self.network
+                .state
+                .lock()
+                .unwrap()
+                .nodes
+                .insert(id.clone(), inbox);
+            tokio::spawn(node(self.writer(), rx));
+            self.node_ids.insert(id);
+        }
+    }
+
+    /// Sends `init` to every node and waits for them to acknowledge it
+    ///
+    /// # Errors
+    ///
+    /// - a node did not answer
+    pub async fn init(&mut self) -> Result<()> {
+        let client = self.client();
+        for node in &self.node_ids {
+            client
+                .call::<_, Value>(
+                    node,
+                    json!({"type": "init", "node_id": node, "node_ids": self.node_ids}),
+                )
+                .await
+                .with_context(|| format!("Initializing {node}"))?;
+        }
+        Ok(())
+    }
+
+    /// A new client, named `c1`, `c2`, ...
+    ///
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    pub fn client(&mut self) -> Client {
+        self.clients += 1;
+        let id = format!("c{}", self.clients);
+        let mut sender = Sender::new(self.writer());
+        sender.set_node_id(&id);
+        self.network
+            .state
+            .lock()
+            .unwrap()
+            .clients
+            .insert(id, sender.replies());
+        Client {
+            output: Arc::new(Mutex::new(sender)),
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[must_use]
+    pub fn node_ids(&self) -> &BTreeSet<String> {
+        &self.node_ids
+    }
+
+    /// Nodes can only talk to nodes in the same group until [`Sim::heal`].
+    /// Nodes that are in no group are isolated.
+    ///
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    pub fn partition(&self, groups: &[&[&str]]) {
+        let mut state = self.network.state.lock().unwrap();
+        state.partitions = self
+            .node_ids
+            .iter()
+            .enumerate()
+            .map(|(isolated, node)| {
+                let group = groups
+                    .iter()
+                    .position(|group| group.contains(&node.as_str()))
+                    .unwrap_or(groups.len() + isolated);
+                (node.clone(), group)
+            })
+            .collect();
+    }
+
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    pub fn heal(&self) {
+        self.network.state.lock().unwrap().partitions.clear();
+    }
+
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    pub fn set_config(&self, config: NetworkConfig) {
+        self.network.state.lock().unwrap().config = config;
+    }
+
+    /// Starts recording every delivered message, see [`Sim::trace`]
+    ///
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    pub fn record_trace(&self) {
+        self.network.state.lock().unwrap().trace = Some(Vec::new());
+    }
+
+    /// Messages delivered since [`Sim::record_trace`], in delivery order
+    ///
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    #[must_use]
+    pub fn trace(&self) -> Vec<Delivery> {
+        self.network
+            .state
+            .lock()
+            .unwrap()
+            .trace
+            .clone()
+            .unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> &NetworkStats {
+        &self.network.stats
+    }
+
+    fn writer(&self) -> SimWriter {
+        SimWriter {
+            buffer: Vec::new(),
+            frames: self.network.frames.clone(),
+        }
+    }
+}
+
+/// Issues requests to the simulated nodes
+#[derive(Clone)]
+pub struct Client {
+    output: Arc<Mutex<Sender<SimWriter>>>,
+    timeout: Duration,
+}
+
+impl Client {
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends `msg_type` to `dest` and waits for the reply
+    ///
+    /// # Panics
+    ///
+    /// - if locks are poisoned
+    ///
+    /// # Errors
+    ///
+    /// - the node replied with an error
+    /// - no reply within the client's timeout
+    pub async fn call<T: Serialize, R: DeserializeOwned>(
+        &self,
+        dest: &str,
+        msg_type: T,
+    ) -> Result<R> {
+        let reply = self
+            .output
+            .lock()
+            .unwrap()
+            .rpc_timeout::<T, R>(dest, msg_type, self.timeout);
+        Ok(reply.await?.body.msg_type)
+    }
+}
+
+/// Runs `program` as a node, feeding it messages on stdin one JSON per line
+/// and reading its replies from stdout. Its stderr is traced at debug level.
+/// Use it with [`Sim::add_nodes`], as in
+/// `sim.add_nodes(3, |writer, input| run_process(program.clone(), writer, input))`.
+/// Closing `input` closes the program's stdin, which is how Maelstrom nodes
+/// are told to stop.
+///
+/// # Errors
+///
+/// - the program cannot be started
+/// - the program exits before its input is closed
+pub async fn run_process(
+    program: PathBuf,
+    writer: SimWriter,
+    input: Receiver<Value>,
+) -> Result<()> {
+    run_command(Command::new(program), writer, input).await
+}
+
+/// Like [`run_process`], for a program that needs arguments or environment
+/// variables
+///
+/// # Errors
+///
+/// - the program cannot be started
+/// - the program exits before its input is closed
+pub async fn run_command(
+    mut command: Command,
+    mut writer: SimWriter,
+    mut input: Receiver<Value>,
+) -> Result<()> {
+    let program = PathBuf::from(command.as_std().get_program());
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Starting {}", program.display()))?;
+    let pid = child.id();
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped")).lines();
+    let mut stderr = BufReader::new(child.stderr.take().expect("stderr is piped")).lines();
+
+    tokio::spawn(async move {
+        while let Ok(Some(line)) = stderr.next_line().await {
+            tracing::debug!(pid, "{line}");
+        }
+    });
+    // Written from its own task: the program may stop reading its input
+    // until we read its output
+    let closed = tokio::spawn(async move {
+        while let Some(value) = input.recv().await {
+            if stdin
+                .write_all(format!("{value}\n").as_bytes())
+                .await
+                .is_err()
+            {
+                return false;
+            }
+        }
+        true
+    });
+
+    while let Some(line) = stdout.next_line().await? {
+        writer.write_all(line.as_bytes())?;
+        writer.flush()?;
+    }
+    child.wait().await?;
+    let input_closed = if closed.is_finished() {
+        closed.await.unwrap_or_default()
+    } else {
+        closed.abort();
+        false
+    };
+    if !input_closed {
+        bail!("{} exited before its input was closed", program.display());
+    }
+    Ok(())
+}
+
+/// Runs `test` on a single thread with virtual time, drawing all randomness
+/// from `seed`. Time only advances when every task is waiting on a timer, so
+/// long timeouts cost nothing.
+///
+/// # Panics
+///
+/// - if the runtime cannot be built
+pub fn deterministic<F, Fut>(seed: u64, test: F) -> Fut::Output
+where
+    F: FnOnce() -> Fut,
+    Fut: Future,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .expect("runtime builds");
+    crate::random::seed(seed);
+    runtime.block_on(test())
+}
+
+/// Finds a smaller workload that still fails. `fails` runs the workload with
+/// the given size, and `size` must be one it fails with. Halves the size
+/// while that keeps failing, then steps down one at a time, so the result
+/// fails and the size right below it does not.
+pub fn shrink(mut size: usize, fails: impl Fn(usize) -> bool) -> usize {
+    while size > 1 && fails(size / 2) {
+        size /= 2;
+    }
+    while size > 0 && fails(size - 1) {
+        size -= 1;
+    }
+    size
+}
+
+/// Pending deliveries are ordered by time, then by the order they were sent
+/// in, so that simultaneous deliveries do not depend on the timer wheel.
+async fn route(network: Arc<Network>, mut frames: UnboundedReceiver<Vec<u8>>) {
+    let mut pending: BTreeMap<(Instant, u64), Value> = BTreeMap::new();
+    let mut sent = 0;
+    loop {
+        let next = pending.first_key_value().map(|((at, _), _)| *at);
+        tokio::select! {
+            biased;
+            frame = frames.recv() => {
+                let Some(frame) = frame else {
+                    break;
+                };
+                let value: Value = match serde_json::from_slice(&frame) {
+                    Ok(value) => value,
+                    Err(error) => {
+                        tracing::warn!(%error, "Node wrote invalid json");
+                        continue;
+                    }
+                };
+                let now = Instant::now();
+                for delay in delays(&network, &value) {
+                    sent += 1;
+                    pending.insert((now + delay, sent), value.clone());
+                }
+            }
+            () = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                if let Some((_, value)) = pending.pop_first() {
+                    deliver(&network, value);
+                }
+            }
+        }
+    }
+}
+
+/// How long each copy of `value` takes to arrive, no copies if it is lost
+fn delays(network: &Network, value: &Value) -> Vec<Duration> {
+    let src = value["src"].as_str().unwrap_or_default();
+    let dest = value["dest"].as_str().unwrap_or_default();
+    let state = network.state.lock().unwrap();
+    let between_nodes = state.nodes.contains_key(src) && state.nodes.contains_key(dest);
+    let mut copies = 1;
+    if between_nodes {
+        network.stats.between_nodes.fetch_add(1, Ordering::Relaxed);
+        let partitioned = match (state.partitions.get(src), state.partitions.get(dest)) {
+            (Some(from), Some(to)) => from != to,
+            _ => false,
+        };
+        let config = &state.config;
+        if partitioned || random::with_rng(|rng| rng.gen_bool(config.drop_rate)) {
+            network.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            copies = 0;
+        } else if random::with_rng(|rng| rng.gen_bool(config.duplicate_rate)) {
+            network.stats.duplicated.fetch_add(1, Ordering::Relaxed);
+            copies = 2;
+        }
+    }
+    let (min, max) = state.config.latency;
+    (0..copies)
+        .map(|_| {
+            if max > min {
+                random::with_rng(|rng| rng.gen_range(min..max))
+            } else {
+                min
+            }
+        })
+        .collect()
+}
+
+fn deliver(network: &Network, value: Value) {
+    let dest = value["dest"].as_str().unwrap_or_default().to_string();
+    let node = {
+        let mut state = network.state.lock().unwrap();
+        let at = state.start.elapsed();
+        if let Some(trace) = &mut state.trace {
+            trace.push(Delivery {
+                at,
+                message: value.clone(),
+            });
+        }
+        if let Some(replies) = state.clients.get(&dest) {
+            let _ = replies.resolve(value);
+            return;
+        }
+        if let Some(kv) = state.kv.get_mut(&dest) {
+            if let Some(reply) = serde_json::from_value::<Message<KvMessage>>(value)
+                .ok()
+                .and_then(|request| kv.reply(&request))
+            {
+                let _ = network.frames.send(reply.to_string().into_bytes());
+            }
+            return;
+        }
+        state.nodes.get(&dest).cloned()
+    };
+
+    match node {
+        Some(node) => {
+            let _ = node.send(value);
+        }
+        None => tracing::warn!(dest, "Message to unknown destination"),
+    }
+}
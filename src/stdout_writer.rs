@@ -1,30 +1,63 @@
-use std::io::{StdoutLock, Write};
+use std::io::Write;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
 
-pub struct StdOutWriter<'a> {
-    stdout: StdoutLock<'a>,
+/// Hands every flushed message to a task that owns stdout, so that writing
+/// never blocks. Messages are written in the order they are flushed.
+pub struct StdOutWriter {
+    buffer: Vec<u8>,
+    frames: UnboundedSender<Vec<u8>>,
 }
 
-impl<'a> Default for StdOutWriter<'a> {
-    fn default() -> Self {
-        Self {
-            stdout: std::io::stdout().lock(),
-        }
+impl StdOutWriter {
+    /// Spawns the task writing to stdout, which finishes once the writer is
+    /// dropped and everything it was handed is written
+    ///
+    /// # Panics
+    ///
+    /// - if called outside of a tokio runtime
+    #[must_use]
+    pub fn spawn() -> (Self, JoinHandle<()>) {
+        let (frames, rx) = unbounded_channel();
+        let written = tokio::spawn(write_frames(rx));
+        let writer = Self {
+            buffer: Vec::new(),
+            frames,
+        };
+        (writer, written)
     }
 }
 
-impl<'a> Write for StdOutWriter<'a> {
-    fn write_all(&mut self, buf: &[u8]) -> Result<(), std::io::Error> {
-        self.stdout.write_all(buf)
+impl Write for StdOutWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
     }
 
-    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
-        self.stdout.write(buf)
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.frames
+            .send(std::mem::take(&mut self.buffer))
+            .map_err(|_| std::io::ErrorKind::BrokenPipe.into())
     }
+}
 
-    fn flush(&mut self) -> Result<(), std::io::Error> {
-        self.stdout.write_all(b"\n")?;
-        self.stdout.flush()
+/// Writes frames as they come, flushing once the queue is empty
+async fn write_frames(mut frames: UnboundedReceiver<Vec<u8>>) {
+    let mut stdout = tokio::io::stdout();
+    while let Some(frame) = frames.recv().await {
+        let mut result = stdout.write_all(&frame).await;
+        while result.is_ok() {
+            let Ok(frame) = frames.try_recv() else {
+                break;
+            };
+            result = stdout.write_all(&frame).await;
+        }
+        if let Err(error) = result.and(stdout.flush().await) {
+            tracing::error!(%error, "Could not write to stdout");
+            break;
+        }
     }
 }
-
-unsafe impl<'a> Send for StdOutWriter<'a> {}
@@ -0,0 +1,235 @@
+//! Strategies to pick who gossips with whom.
+//!
+//! Maelstrom suggests a topology to broadcast nodes, but fewer or better
+//! placed links can cut the messages sent per operation or the time values
+//! take to spread. A [`Topology`] is picked at runtime from the `TOPOLOGY`
+//! environment variable, see [`from_name`].
+
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// Environment variable [`from_env`] reads
+pub const ENV_VAR: &str = "TOPOLOGY";
+
+/// The neighbors of every node. Links go both ways.
+pub type Graph = BTreeMap<String, BTreeSet<String>>;
+
+pub trait Topology: Send {
+    /// Links between `nodes`, `suggested` is the topology Maelstrom sent
+    fn graph(&self, nodes: &BTreeSet<String>, suggested: &Graph) -> Graph;
+}
+
+/// Whatever Maelstrom suggested
+pub struct Maelstrom;
+
+/// Every node with every other
+pub struct FullMesh;
+
+/// A breadth-first spanning tree of Maelstrom's suggestion: every node is
+/// still reachable, with as few links as possible
+pub struct SpanningTree;
+
+/// Nodes in a tree where each has up to `arity` children
+pub struct KAryTree {
+    pub arity: usize,
+}
+
+/// Nodes laid out row by row on a square grid, linked to the nodes above,
+/// below, left and right
+pub struct Grid;
+
+/// A ring of the nodes in a random order, each also linked to the nodes up to
+/// `degree / 2` positions away (and across the ring for an odd degree), so
+/// that every node has `degree` neighbors. When the node count is odd the
+/// degree is rounded down to an even one. The same `seed` gives the same
+/// graph on every node.
+pub struct RandomRegular {
+    pub degree: usize,
+    pub seed: u64,
+}
+
+fn empty(nodes: &BTreeSet<String>) -> Graph {
+    nodes
+        .iter()
+        .map(|node| (node.clone(), BTreeSet::new()))
+        .collect()
+}
+
+fn link(graph: &mut Graph, a: &str, b: &str) {
+    if a == b {
+        return;
+    }
+    graph
+        .entry(a.to_string())
+        .or_default()
+        .insert(b.to_string());
+    graph
+        .entry(b.to_string())
+        .or_default()
+        .insert(a.to_string());
+}
+
+impl Topology for Maelstrom {
+    fn graph(&self, nodes: &BTreeSet<String>, suggested: &Graph) -> Graph {
+        let mut graph = empty(nodes);
+        for (node, neighbors) in suggested {
+            for neighbor in neighbors {
+                link(&mut graph, node, neighbor);
+            }
+        }
+        graph
+    }
+}
+
+impl Topology for FullMesh {
+    fn graph(&self, nodes: &BTreeSet<String>, _suggested: &Graph) -> Graph {
+        let mut graph = empty(nodes);
+        for a in nodes {
+            for b in nodes {
+                link(&mut graph, a, b);
+            }
+        }
+        graph
+    }
+}
+
+impl Topology for SpanningTree {
+    fn graph(&self, nodes: &BTreeSet<String>, suggested: &Graph) -> Graph {
+        let suggested = Maelstrom.graph(nodes, suggested);
+        let mut graph = empty(nodes);
+        let mut visited = BTreeSet::new();
+        let Some(root) = nodes.first() else {
+            return graph;
+        };
+        // Parts the suggestion leaves disconnected hang from the root
+        for start in nodes {
+            if !visited.insert(start) {
+                continue;
+            }
+            link(&mut graph, root, start);
+            let mut queue = VecDeque::from([start]);
+            while let Some(node) = queue.pop_front() {
+                for neighbor in suggested.get(node).into_iter().flatten() {
+                    if visited.insert(neighbor) {
+                        link(&mut graph, node, neighbor);
+                        queue.push_back(neighbor);
+                    }
+                }
+            }
+        }
+        graph
+    }
+}
+
+impl Topology for KAryTree {
+    fn graph(&self, nodes: &BTreeSet<String>, _suggested: &Graph) -> Graph {
+        let mut graph = empty(nodes);
+        let nodes: Vec<_> = nodes.iter().collect();
+        for (i, node) in nodes.iter().enumerate().skip(1) {
+            link(&mut graph, nodes[(i - 1) / self.arity.max(1)], node);
+        }
+        graph
+    }
+}
+
+impl Topology for Grid {
+    fn graph(&self, nodes: &BTreeSet<String>, _suggested: &Graph) -> Graph {
+        let mut graph = empty(nodes);
+        let nodes: Vec<_> = nodes.iter().collect();
+        let width = (1..=nodes.len())
+            .find(|width| width * width >= nodes.len())
+            .unwrap_or(1);
+        for (i, node) in nodes.iter().enumerate() {
+            if i % width + 1 < width && i + 1 < nodes.len() {
+                link(&mut graph, node, nodes[i + 1]);
+            }
+            if i + width < nodes.len() {
+                link(&mut graph, node, nodes[i + width]);
+            }
+        }
+        graph
+    }
+}
+
+impl Topology for RandomRegular {
+    fn graph(&self, nodes: &BTreeSet<String>, _suggested: &Graph) -> Graph {
+        let mut graph = empty(nodes);
+        let mut ring: Vec<_> = nodes.iter().collect();
+        let count = ring.len();
+        // Fisher-Yates with splitmix64, so that every node agrees on the order
+        // without depending on the `rand` feature
+        let mut state = self.seed;
+        for i in (1..count).rev() {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            ring.swap(i, usize::try_from(z % (i as u64 + 1)).unwrap_or_default());
+        }
+
+        let degree = self.degree.min(count.saturating_sub(1));
+        for (i, node) in ring.iter().enumerate() {
+            for offset in 1..=degree / 2 {
+                link(&mut graph, node, ring[(i + offset) % count]);
+            }
+            if degree % 2 == 1 && count % 2 == 0 {
+                link(&mut graph, node, ring[(i + count / 2) % count]);
+            }
+        }
+        graph
+    }
+}
+
+/// Parses a topology name:
+///
+/// - `maelstrom`: [`Maelstrom`]
+/// - `mesh`: [`FullMesh`]
+/// - `spanning-tree`: [`SpanningTree`]
+/// - `tree:K`: [`KAryTree`] with arity `K`, 4 if omitted
+/// - `grid`: [`Grid`]
+/// - `random:K:SEED`: [`RandomRegular`] of degree `K` (3 if omitted) built
+///   from `SEED` (0 if omitted)
+///
+/// # Errors
+///
+/// - the name or its parameters are not valid
+pub fn from_name(name: &str) -> Result<Box<dyn Topology>> {
+    let mut parts = name.split(':');
+    let kind = parts.next().unwrap_or_default();
+    let mut parameter = |default: u64| -> Result<u64> {
+        parts.next().map_or(Ok(default), |parameter| {
+            parameter
+                .parse()
+                .with_context(|| format!("Invalid parameter {parameter:?} in topology {name:?}"))
+        })
+    };
+    let topology: Box<dyn Topology> = match kind {
+        "maelstrom" => Box::new(Maelstrom),
+        "mesh" => Box::new(FullMesh),
+        "spanning-tree" => Box::new(SpanningTree),
+        "tree" => Box::new(KAryTree {
+            arity: usize::try_from(parameter(4)?)?,
+        }),
+        "grid" => Box::new(Grid),
+        "random" => Box::new(RandomRegular {
+            degree: usize::try_from(parameter(3)?)?,
+            seed: parameter(0)?,
+        }),
+        other => bail!("Unknown topology {other:?}"),
+    };
+    Ok(topology)
+}
+
+/// The topology named by the `TOPOLOGY` environment variable, [`Maelstrom`]
+/// when it is not set
+///
+/// # Errors
+///
+/// - the variable does not name a valid topology
+pub fn from_env() -> Result<Box<dyn Topology>> {
+    match std::env::var(ENV_VAR) {
+        Ok(name) => from_name(&name),
+        Err(_) => Ok(Box::new(Maelstrom)),
+    }
+}
@@ -1 +1,2 @@
+pub mod replica;
 pub mod store;
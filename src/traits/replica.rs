@@ -0,0 +1,44 @@
+use crate::crdt::Crdt;
+use crate::log::Log;
+use crate::traits::store::Store;
+use serde::{de::DeserializeOwned, Serialize};
+use std::ops::DerefMut;
+
+/// A node state holding a [`Crdt`], which [`crate::gossip`] replicates.
+///
+/// Every [`Store`] is a replica of its [`Log`], and hears of the values
+/// merged into it through [`Store::new_value`].
+pub trait Replica {
+    type Crdt: Crdt;
+
+    fn crdt(&self) -> &Self::Crdt;
+
+    fn crdt_mut(&mut self) -> &mut Self::Crdt;
+
+    /// Merges what another replica sent
+    fn merge(&mut self, delta: &Self::Crdt) {
+        self.crdt_mut().merge(delta);
+    }
+}
+
+impl<T, S> Replica for S
+where
+    T: Clone + PartialEq + Serialize + DeserializeOwned,
+    S: Store<T> + DerefMut<Target = Log<T>>,
+{
+    type Crdt = Log<T>;
+
+    fn crdt(&self) -> &Log<T> {
+        self
+    }
+
+    fn crdt_mut(&mut self) -> &mut Log<T> {
+        self
+    }
+
+    fn merge(&mut self, delta: &Log<T>) {
+        for val in self.deref_mut().merge_from(delta) {
+            self.new_value(&val);
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use serde_json::{json, Value};
+use std::time::Duration;
+use symmetrical_octo_potato::{
+    checker::{self, Consistency},
+    history::{Operation, Outcome},
+    message::{ErrorCode, MaelstromError},
+};
+
+fn ok(reply: Value) -> Outcome {
+    Outcome::Ok { reply }
+}
+
+fn fail(code: ErrorCode) -> Outcome {
+    Outcome::Fail {
+        error: MaelstromError::new(code, ""),
+    }
+}
+
+fn info() -> Outcome {
+    Outcome::Info {
+        error: MaelstromError::new(ErrorCode::Timeout, ""),
+    }
+}
+
+/// An operation on `node` that ran from `invoked` to `completed`
+/// milliseconds
+fn op(node: &str, (invoked, completed): (u64, u64), request: Value, outcome: Outcome) -> Operation {
+    Operation {
+        process: "c1".to_string(),
+        node: node.to_string(),
+        request,
+        outcome,
+        invoked: Duration::from_millis(invoked),
+        completed: Duration::from_millis(completed),
+    }
+}
+
+#[test]
+fn broadcast_needs_every_acknowledged_message_everywhere() {
+    let mut history = vec![
+        op(
+            "n1",
+            (0, 1),
+            json!({"type": "broadcast", "message": 1}),
+            ok(json!({})),
+        ),
+        op(
+            "n2",
+            (0, 1),
+            json!({"type": "broadcast", "message": 2}),
+            info(),
+        ),
+        op(
+            "n1",
+            (5, 6),
+            json!({"type": "read"}),
+            ok(json!({"messages": [1]})),
+        ),
+        op(
+            "n2",
+            (5, 6),
+            json!({"type": "read"}),
+            ok(json!({"messages": [1, 2]})),
+        ),
+    ];
+    assert_eq!(checker::broadcast(&history), Ok(()));
+
+    history.push(op(
+        "n1",
+        (7, 8),
+        json!({"type": "read"}),
+        ok(json!({"messages": [2, 3]})),
+    ));
+    let anomalies = checker::broadcast(&history).unwrap_err().0;
+    assert_eq!(anomalies.len(), 2, "{anomalies:?}");
+}
+
+#[test]
+fn g_counter_final_reads_sum_acknowledged_adds() {
+    let mut history = vec![
+        op(
+            "n1",
+            (0, 1),
+            json!({"type": "add", "delta": 2}),
+            ok(json!({})),
+        ),
+        op("n2", (0, 1), json!({"type": "add", "delta": 3}), info()),
+        op(
+            "n2",
+            (0, 1),
+            json!({"type": "add", "delta": 4}),
+            fail(ErrorCode::Abort),
+        ),
+        op(
+            "n1",
+            (5, 6),
+            json!({"type": "read"}),
+            ok(json!({"value": 2})),
+        ),
+        op(
+            "n2",
+            (5, 6),
+            json!({"type": "read"}),
+            ok(json!({"value": 5})),
+        ),
+    ];
+    assert_eq!(checker::g_counter(&history), Ok(()));
+
+    history.push(op(
+        "n1",
+        (7, 8),
+        json!({"type": "read"}),
+        ok(json!({"value": 1})),
+    ));
+    assert!(checker::g_counter(&history).is_err());
+}
+
+#[test]
+fn pn_counter_reads_stay_within_possible_sums() {
+    let mut history = vec![
+        op(
+            "n1",
+            (0, 1),
+            json!({"type": "add", "delta": 2}),
+            ok(json!({})),
+        ),
+        op("n2", (0, 1), json!({"type": "add", "delta": -3}), info()),
+        op(
+            "n2",
+            (0, 1),
+            json!({"type": "add", "delta": 4}),
+            fail(ErrorCode::Abort),
+        ),
+        op(
+            "n1",
+            (5, 6),
+            json!({"type": "read"}),
+            ok(json!({"value": 2})),
+        ),
+        op(
+            "n2",
+            (5, 6),
+            json!({"type": "read"}),
+            ok(json!({"value": -1})),
+        ),
+    ];
+    assert_eq!(checker::pn_counter(&history), Ok(()));
+
+    history.push(op(
+        "n1",
+        (7, 8),
+        json!({"type": "read"}),
+        ok(json!({"value": 6})),
+    ));
+    assert!(checker::pn_counter(&history).is_err());
+}
+
+#[test]
+fn kafka_polls_do_not_skip_acknowledged_offsets() {
+    let send = |msg, offset| {
+        op(
+            "n1",
+            (0, 1),
+            json!({"type": "send", "key": "k", "msg": msg}),
+            ok(json!({"offset": offset})),
+        )
+    };
+    let poll = |node, messages: Value| {
+        op(
+            node,
+            (5, 6),
+            json!({"type": "poll", "offsets": {"k": 0}}),
+            ok(json!({"msgs": {"k": messages}})),
+        )
+    };
+
+    let history = vec![
+        send(10, 0),
+        send(11, 1),
+        send(12, 2),
+        poll("n1", json!([[0, 10], [1, 11], [2, 12]])),
+    ];
+    assert_eq!(checker::kafka(&history), Ok(()));
+
+    // Offset 2 was never written, which polls may step over
+    let gap = vec![
+        send(10, 0),
+        send(11, 1),
+        send(12, 3),
+        poll("n1", json!([[0, 10], [1, 11], [3, 12]])),
+    ];
+    assert_eq!(checker::kafka(&gap), Ok(()));
+
+    // Offset 1 holds a message whose send was never acknowledged
+    let hole = vec![
+        send(10, 0),
+        send(12, 2),
+        poll("n1", json!([[0, 10], [1, 11], [2, 12]])),
+        poll("n2", json!([[0, 10], [2, 12]])),
+    ];
+    assert!(checker::kafka(&hole).is_err());
+
+    let skipped = vec![send(10, 0), send(11, 1), poll("n1", json!([[0, 10]]))];
+    assert!(checker::kafka(&skipped).is_err());
+
+    let reordered = vec![
+        send(10, 0),
+        send(11, 1),
+        poll("n1", json!([[1, 11], [0, 10]])),
+    ];
+    assert!(checker::kafka(&reordered).is_err());
+
+    let inconsistent = vec![send(10, 0), poll("n1", json!([[0, 11]]))];
+    assert!(checker::kafka(&inconsistent).is_err());
+}
+
+#[test]
+fn lin_kv_finds_stale_reads() {
+    let write = |(invoked, completed), value| {
+        op(
+            "n1",
+            (invoked, completed),
+            json!({"type": "write", "key": 1, "value": value}),
+            ok(json!({"type": "write_ok"})),
+        )
+    };
+    let read = |(invoked, completed), value| {
+        op(
+            "n2",
+            (invoked, completed),
+            json!({"type": "read", "key": 1}),
+            ok(json!({"type": "read_ok", "value": value})),
+        )
+    };
+
+    // Concurrent with the write, the read may see either value
+    let concurrent = vec![write((0, 1), 1), write((2, 10), 2), read((3, 4), 1)];
+    assert_eq!(checker::lin_kv(&concurrent), Ok(()));
+
+    // Once the write completed, reads must see it
+    let stale = vec![write((0, 1), 1), write((2, 3), 2), read((4, 5), 1)];
+    assert!(checker::lin_kv(&stale).is_err());
+
+    // A timed out cas may have happened...
+    let cas = |outcome| {
+        op(
+            "n1",
+            (2, 3),
+            json!({"type": "cas", "key": 1, "from": 1, "to": 3}),
+            outcome,
+        )
+    };
+    let history = vec![write((0, 1), 1), cas(info()), read((4, 5), 3)];
+    assert_eq!(checker::lin_kv(&history), Ok(()));
+    let history = vec![write((0, 1), 1), cas(info()), read((4, 5), 1)];
+    assert_eq!(checker::lin_kv(&history), Ok(()));
+    // ...but a failed one did not
+    let history = vec![
+        write((0, 1), 1),
+        cas(fail(ErrorCode::PreconditionFailed)),
+        read((4, 5), 1),
+    ];
+    assert!(checker::lin_kv(&history).is_err());
+}
+
+#[test]
+fn lin_kv_lets_cas_create_missing_keys() {
+    let cas = |(invoked, completed), from, to, create: bool| {
+        op(
+            "n1",
+            (invoked, completed),
+            json!({"type": "cas", "key": 1, "from": from, "to": to, "create_if_not_exists": create}),
+            ok(json!({"type": "cas_ok"})),
+        )
+    };
+    let read = |(invoked, completed), value| {
+        op(
+            "n2",
+            (invoked, completed),
+            json!({"type": "read", "key": 1}),
+            ok(json!({"type": "read_ok", "value": value})),
+        )
+    };
+
+    let created = vec![
+        cas((0, 1), 0, 1, true),
+        read((2, 3), 1),
+        cas((4, 5), 1, 2, true),
+    ];
+    assert_eq!(checker::lin_kv(&created), Ok(()));
+
+    // Without the flag a missing key cannot be swapped
+    let missing = vec![cas((0, 1), 0, 1, false), read((2, 3), 1)];
+    assert!(checker::lin_kv(&missing).is_err());
+    // Nor can an existing key holding something else
+    let mismatch = vec![cas((0, 1), 0, 1, true), cas((2, 3), 0, 2, true)];
+    assert!(checker::lin_kv(&mismatch).is_err());
+}
+
+#[test]
+fn lin_kv_checks_long_histories() {
+    let history: Vec<_> = (0..5000)
+        .map(|i| {
+            op(
+                "n1",
+                (2 * i, 2 * i + 1),
+                json!({"type": "write", "key": 1, "value": i}),
+                ok(json!({"type": "write_ok"})),
+            )
+        })
+        .collect();
+    assert_eq!(checker::lin_kv(&history), Ok(()));
+}
+
+#[test]
+fn txn_rw_register_finds_g0_and_g1_anomalies() {
+    let txn = |ops: Value, outcome: fn(Value) -> Outcome| {
+        let reply = json!({"type": "txn_ok", "txn": ops});
+        op(
+            "n1",
+            (0, 1),
+            json!({"type": "txn", "txn": ops}),
+            outcome(reply),
+        )
+    };
+    let failed = |_| fail(ErrorCode::Abort);
+    let check = |history: &[Operation]| {
+        [Consistency::ReadUncommitted, Consistency::ReadCommitted]
+            .map(|consistency| checker::txn_rw_register(history, consistency).is_ok())
+    };
+
+    let valid = vec![
+        txn(json!([["w", 1, 1]]), ok),
+        txn(json!([["r", 1, 1], ["w", 1, 2], ["r", 1, 2]]), ok),
+        txn(json!([["r", 1, 2], ["r", 2, null]]), ok),
+    ];
+    assert_eq!(check(&valid), [true, true]);
+
+    let aborted_read = vec![
+        txn(json!([["w", 1, 1]]), failed),
+        txn(json!([["r", 1, 1]]), ok),
+    ];
+    assert_eq!(check(&aborted_read), [true, false]);
+
+    let intermediate_read = vec![
+        txn(json!([["w", 1, 1], ["w", 1, 2]]), ok),
+        txn(json!([["r", 1, 1]]), ok),
+    ];
+    assert_eq!(check(&intermediate_read), [true, false]);
+
+    let write_cycle = vec![
+        txn(json!([["w", 1, 1], ["r", 2, 2], ["w", 2, 3]]), ok),
+        txn(json!([["w", 2, 2], ["r", 1, 1], ["w", 1, 4]]), ok),
+    ];
+    assert_eq!(check(&write_cycle), [false, false]);
+
+    let read_cycle = vec![
+        txn(json!([["w", 1, 1], ["r", 2, 2]]), ok),
+        txn(json!([["w", 2, 2], ["r", 1, 1]]), ok),
+    ];
+    assert_eq!(check(&read_cycle), [true, false]);
+}
+
+#[test]
+fn txn_rw_register_orders_blind_writes_by_final_reads() {
+    let txn = |node, times, ops: Value| {
+        let reply = json!({"type": "txn_ok", "txn": ops});
+        op(node, times, json!({"type": "txn", "txn": ops}), ok(reply))
+    };
+    let writes = vec![
+        txn("n1", (0, 2), json!([["w", 1, 1], ["w", 2, 1]])),
+        txn("n2", (1, 3), json!([["w", 1, 2], ["w", 2, 2]])),
+    ];
+    let finally = |(x, y): (u64, u64)| {
+        let mut history = writes.clone();
+        for node in ["n1", "n2"] {
+            history.push(txn(node, (4, 5), json!([["r", 1, x], ["r", 2, y]])));
+        }
+        checker::txn_rw_register(&history, Consistency::ReadUncommitted)
+    };
+
+    // One transaction overwrote the other on every key
+    assert_eq!(finally((2, 2)), Ok(()));
+    assert_eq!(finally((1, 1)), Ok(()));
+
+    // Each overwrote the other on one key
+    let Err(anomalies) = finally((2, 1)) else {
+        panic!("blind write cycle not found");
+    };
+    assert!(anomalies.0.iter().any(|anomaly| anomaly.starts_with("G0")));
+
+    // Reads before every write completed are not final
+    let mut concurrent = writes.clone();
+    concurrent.push(txn("n1", (2, 5), json!([["r", 1, 2], ["r", 2, 1]])));
+    assert_eq!(
+        checker::txn_rw_register(&concurrent, Consistency::ReadUncommitted),
+        Ok(())
+    );
+
+    // Nodes must agree on what came last
+    let mut diverged = writes;
+    diverged.push(txn("n1", (4, 5), json!([["r", 1, 1], ["r", 2, 1]])));
+    diverged.push(txn("n2", (4, 5), json!([["r", 1, 2], ["r", 2, 2]])));
+    assert!(checker::txn_rw_register(&diverged, Consistency::ReadUncommitted).is_err());
+}
+
+#[test]
+fn txn_rw_register_finds_long_cycles() {
+    // Each transaction overwrites what the previous one wrote, the first
+    // what the last one wrote
+    let count = 20_000;
+    let history: Vec<_> = (0..count)
+        .map(|i| {
+            let ops = json!([["r", 1, (i + count - 1) % count], ["w", 1, i]]);
+            let reply = json!({"type": "txn_ok", "txn": ops});
+            op("n1", (0, 1), json!({"type": "txn", "txn": ops}), ok(reply))
+        })
+        .collect();
+    assert!(checker::txn_rw_register(&history, Consistency::ReadUncommitted).is_err());
+}
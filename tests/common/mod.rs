@@ -0,0 +1,31 @@
+use serde_json::Value;
+use std::io::Write;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Hands every flushed frame to the test
+pub struct Channel {
+    buffer: Vec<u8>,
+    tx: UnboundedSender<Value>,
+}
+
+impl Channel {
+    pub fn new(tx: UnboundedSender<Value>) -> Self {
+        Self {
+            buffer: Vec::new(),
+            tx,
+        }
+    }
+}
+
+impl Write for Channel {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let frame = serde_json::from_slice(&std::mem::take(&mut self.buffer))?;
+        let _ = self.tx.send(frame);
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeSet;
+use symmetrical_octo_potato::{
+    crdt::{Crdt, GCounter, GSet, LwwRegister, Map, MvRegister, OrSet, PnCounter, TwoPSet},
+    init_state::{Init, Initable},
+    log::Log,
+};
+
+/// Merging `a` and `b` either way, twice or from their deltas, converges
+fn assert_converges<C: Crdt + std::fmt::Debug>(a: &C, b: &C) {
+    let mut ab = a.clone();
+    ab.merge(b);
+    let mut ba = b.clone();
+    ba.merge(a);
+    assert_eq!(ab, ba);
+
+    let mut twice = ab.clone();
+    twice.merge(b);
+    assert_eq!(twice, ab);
+
+    let mut from_delta = a.clone();
+    from_delta.merge(&b.delta(&a.summary()));
+    assert_eq!(from_delta, ab);
+    assert!(ab.delta(&ba.summary()).is_empty());
+
+    // Summaries keep up with the deltas merged
+    let mut summary = a.summary();
+    b.delta(&summary).summarize_into(&mut summary);
+    assert!(ab.delta(&summary).is_empty());
+}
+
+#[test]
+fn counters_add_up_every_node() {
+    let mut a = GCounter::default();
+    let mut b = GCounter::default();
+    a.increment("n1", 2);
+    b.increment("n1", 1);
+    b.increment("n2", 3);
+    assert_converges(&a, &b);
+    a.merge(&b);
+    assert_eq!(a.value(), 5);
+
+    let mut a = PnCounter::default();
+    let mut b = PnCounter::default();
+    a.add("n1", 4);
+    b.add("n2", -6);
+    assert_converges(&a, &b);
+    a.merge(&b);
+    assert_eq!(a.value(), -2);
+
+    // Totals beyond i64 saturate instead of wrapping
+    let mut c = PnCounter::default();
+    c.add("n1", i64::MAX);
+    c.add("n2", i64::MAX);
+    assert_eq!(c.value(), i64::MAX);
+    c.add("n1", i64::MIN);
+    c.add("n2", i64::MIN);
+    c.add("n3", i64::MIN);
+    assert_eq!(c.value(), i64::MIN);
+}
+
+#[test]
+fn maps_merge_and_send_each_key_apart() {
+    let mut a: Map<String, GCounter> = Map::default();
+    let mut b = a.clone();
+    a.entry("x".to_string()).increment("n1", 1);
+    b.entry("x".to_string()).increment("n2", 2);
+    b.entry("y".to_string()).increment("n2", 3);
+    assert_converges(&a, &b);
+
+    let mut merged = a.clone();
+    merged.merge(&b);
+    assert_eq!(merged.get(&"x".to_string()).map(Crdt::value), Some(3));
+    let delta = merged.delta(&b.summary());
+    assert!(delta.get(&"y".to_string()).is_none());
+}
+
+#[test]
+fn removals_win_in_two_phase_sets() {
+    let mut a = TwoPSet::default();
+    a.insert(1);
+    a.insert(2);
+    let mut b = a.clone();
+    b.remove(&1);
+    a.insert(1);
+    assert_converges(&a, &b);
+    a.merge(&b);
+    assert_eq!(a.value(), BTreeSet::from([2]));
+
+    let mut a = GSet::default();
+    let mut b = GSet::default();
+    a.insert(1);
+    b.insert(2);
+    assert_converges(&a, &b);
+}
+
+#[test]
+fn concurrent_additions_win_in_or_sets() {
+    let mut a = OrSet::default();
+    a.insert("n1", 1);
+    let mut b = a.clone();
+    b.remove(&1);
+    a.insert("n1", 1);
+    assert_converges(&a, &b);
+    a.merge(&b);
+    assert!(a.contains(&1));
+
+    b.merge(&a);
+    b.remove(&1);
+    a.merge(&b);
+    assert!(a.value().is_empty());
+}
+
+#[test]
+fn registers_keep_the_last_or_concurrent_writes() {
+    let mut a = LwwRegister::default();
+    a.set("n1", 1);
+    let mut b = a.clone();
+    b.set("n2", 2);
+    assert_converges(&a, &b);
+    a.merge(&b);
+    assert_eq!(a.value(), Some(2));
+
+    let mut a = MvRegister::default();
+    a.set("n1", 1);
+    let mut b = a.clone();
+    a.set("n1", 2);
+    b.set("n2", 3);
+    assert_converges(&a, &b);
+    a.merge(&b);
+    assert_eq!(
+        a.value().into_iter().collect::<BTreeSet<_>>(),
+        BTreeSet::from([2, 3])
+    );
+
+    a.set("n1", 4);
+    b.merge(&a);
+    assert_eq!(b.value(), vec![4]);
+}
+
+#[test]
+fn logs_are_summarized_by_their_version() {
+    let log = |node: &str| {
+        Log::with_init(Init {
+            node_id: node.to_string(),
+            node_ids: BTreeSet::new(),
+        })
+    };
+    let mut a = log("n1");
+    let mut b = log("n2");
+    let _ = a.insert(&1);
+    let _ = b.insert(&2);
+    let _ = b.insert(&3);
+    assert_converges(&a, &b);
+
+    let summary = b.summary();
+    assert_eq!((summary.get("n1"), summary.get("n2")), (0, 2));
+    assert_eq!(&summary, b.version());
+
+    // Values past a missing one are not counted, and will be sent again
+    let since = b.summary();
+    let _ = b.insert(&4);
+    let delta = b.delta(&since);
+    assert_eq!(delta.value(), [4]);
+    let mut summary = a.summary();
+    delta.summarize_into(&mut summary);
+    assert_eq!(summary.get("n2"), 0);
+}
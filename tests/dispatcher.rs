@@ -0,0 +1,73 @@
+mod common;
+
+use common::Channel;
+use serde_json::{json, Value};
+use std::{sync::atomic::Ordering, time::Duration};
+use symmetrical_octo_potato::{
+    dispatcher::{Dispatcher, QUEUE_CAPACITY},
+    sender::Sender,
+};
+use tokio::sync::mpsc::unbounded_channel;
+
+fn dispatcher() -> Dispatcher {
+    let (tx, _) = unbounded_channel();
+    Dispatcher::new(Sender::new(Channel::new(tx)).replies())
+}
+
+fn message(msg_type: &str, value: usize) -> Value {
+    json!({"src": "n2", "dest": "n1", "body": {"type": msg_type, "value": value}})
+}
+
+#[tokio::test]
+async fn routes_each_type_to_its_handler() {
+    let mut dispatcher = dispatcher();
+    let mut gossip = dispatcher.register(&["gossip", "gossip_ok"]);
+    let mut fallback = dispatcher.register_fallback();
+
+    dispatcher.dispatch(message("gossip", 1));
+    dispatcher.dispatch(message("echo", 2));
+    dispatcher.dispatch(message("gossip_ok", 3));
+
+    assert_eq!(gossip.recv().await.unwrap()["body"]["value"], 1);
+    assert_eq!(gossip.recv().await.unwrap()["body"]["value"], 3);
+    assert_eq!(fallback.recv().await.unwrap()["body"]["value"], 2);
+    assert_eq!(dispatcher.stats().unroutable.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn counts_messages_nobody_registered_for() {
+    let mut dispatcher = dispatcher();
+    let _gossip = dispatcher.register(&["gossip"]);
+
+    dispatcher.dispatch(message("echo", 1));
+    assert_eq!(dispatcher.stats().unroutable.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn lagging_handler_does_not_hold_up_the_others() {
+    let mut dispatcher = dispatcher();
+    let mut slow = dispatcher.register(&["gossip"]);
+    let mut echo = dispatcher.register(&["echo"]);
+
+    // Fills the queue, then the backlog in front of it, then overflows
+    for value in 0..QUEUE_CAPACITY {
+        dispatcher.dispatch(message("gossip", value));
+    }
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    for value in QUEUE_CAPACITY..3 * QUEUE_CAPACITY {
+        dispatcher.dispatch(message("gossip", value));
+    }
+    dispatcher.dispatch(message("echo", 0));
+    let reply = tokio::time::timeout(Duration::from_secs(1), echo.recv()).await;
+    assert_eq!(reply.unwrap().unwrap()["body"]["value"], 0);
+
+    let stats = dispatcher.stats();
+    assert!(stats.lagged.load(Ordering::Relaxed) > 0);
+    assert_eq!(stats.dropped.load(Ordering::Relaxed), QUEUE_CAPACITY);
+
+    // What fit is delivered in order once the handler catches up
+    for value in 0..2 * QUEUE_CAPACITY {
+        assert_eq!(slow.recv().await.unwrap()["body"]["value"], value);
+    }
+    assert!(slow.try_recv().is_err());
+}
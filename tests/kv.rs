@@ -0,0 +1,78 @@
+mod common;
+
+use common::Channel;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use symmetrical_octo_potato::{
+    kv::{KvError, KvMessage, LinKv, LocalKv, SeqKv},
+    message::{ErrorCode, Message},
+    sender::Sender,
+};
+use tokio::sync::mpsc::unbounded_channel;
+
+fn local_kv() -> Arc<Mutex<Sender<Channel>>> {
+    let (tx, mut rx) = unbounded_channel();
+    let mut sender = Sender::new(Channel::new(tx));
+    sender.set_node_id("n1");
+    let replies = sender.replies();
+    tokio::spawn(async move {
+        let mut kv = LocalKv::default();
+        while let Some(frame) = rx.recv().await {
+            let request: Message<KvMessage> = serde_json::from_value(frame).unwrap();
+            if let Some(reply) = kv.reply(&request) {
+                let _ = replies.resolve(reply);
+            }
+        }
+    });
+    Arc::new(Mutex::new(sender))
+}
+
+#[tokio::test]
+async fn read_write_cas() {
+    let kv = SeqKv::new(local_kv());
+
+    assert!(matches!(
+        kv.read::<usize>("counter").await,
+        Err(KvError::KeyDoesNotExist)
+    ));
+    assert!(matches!(
+        kv.cas("counter", 0, 1, false).await,
+        Err(KvError::KeyDoesNotExist)
+    ));
+    kv.cas("counter", 0, 1, true).await.unwrap();
+    assert_eq!(kv.read::<usize>("counter").await.unwrap(), 1);
+
+    assert!(matches!(
+        kv.cas("counter", 0, 2, false).await,
+        Err(KvError::PreconditionFailed)
+    ));
+    kv.cas("counter", 1, 2, false).await.unwrap();
+    kv.write("other", vec![1, 2]).await.unwrap();
+    assert_eq!(kv.read::<usize>("counter").await.unwrap(), 2);
+    assert_eq!(kv.read::<Vec<usize>>("other").await.unwrap(), vec![1, 2]);
+}
+
+#[tokio::test]
+async fn unanswered_calls_time_out() {
+    let kv = LinKv::new(Arc::new(Mutex::new(Sender::new(Vec::new()))))
+        .with_timeout(std::time::Duration::from_millis(10));
+
+    match kv.read::<Value>(1).await {
+        Err(error @ KvError::Failed(_)) => assert!(!error.is_definite()),
+        _ => panic!("expected a timeout"),
+    }
+}
+
+#[tokio::test]
+async fn undecodable_values_are_indefinite() {
+    let kv = SeqKv::new(local_kv());
+    kv.write("name", "n1").await.unwrap();
+
+    match kv.read::<usize>("name").await {
+        Err(KvError::Failed(error)) => {
+            assert_eq!(error.code, ErrorCode::Crash);
+            assert!(!error.is_definite());
+        }
+        _ => panic!("expected a decoding failure"),
+    }
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use anyhow::Result;
+use common::Channel;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+use symmetrical_octo_potato::{
+    init_state::{Init, InitState, Initable},
+    message::{Body, Message},
+    protocol::MaelstromProtocol,
+    read_messages,
+    sender::{Batching, Sender},
+    InputStats, Node,
+};
+use tokio::sync::mpsc::{self, unbounded_channel};
+
+struct NoState;
+
+impl Initable for NoState {
+    fn with_init(_init: Init) -> Self {
+        Self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum EchoMessage {
+    Echo { echo: Value },
+    EchoOk { echo: Value },
+}
+
+fn handle_echo(
+    input: &Message<EchoRequest>,
+    output: &Arc<Mutex<Sender<Channel>>>,
+    _state: &Arc<Mutex<InitState<NoState>>>,
+) -> Result<()> {
+    let EchoRequest::Echo { ref echo } = input.body.msg_type;
+    output
+        .lock()
+        .unwrap()
+        .reply_to(input, EchoMessage::echo_ok(echo.clone()))
+}
+
+#[tokio::test]
+async fn reads_one_message_per_line() {
+    let input = b"{\"src\": \"c1\"}\nnot json\n\n{\"src\": \"c2\"}\n".as_slice();
+    let (tx, mut rx) = mpsc::channel(8);
+    let stats = Arc::new(InputStats::default());
+    read_messages(input, tx, stats.clone()).await;
+
+    assert_eq!(rx.recv().await, Some(json!({"src": "c1"})));
+    assert_eq!(rx.recv().await, Some(json!({"src": "c2"})));
+    assert_eq!(rx.recv().await, None);
+    assert_eq!(stats.malformed.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn shuts_down_once_input_is_closed() {
+    let (frames, mut written) = unbounded_channel();
+    let (tx, input) = mpsc::channel(8);
+    let node = Node::<NoState, Channel>::new()
+        .with_batching(Batching {
+            max_size: 8,
+            max_delay: Duration::from_secs(3600),
+        })
+        .with_timer(Duration::from_millis(1), |output, _state| {
+            let _ = output.lock().unwrap().send(
+                Message {
+                    src: "n1".to_string(),
+                    dest: "n2".to_string(),
+                    body: Body {
+                        msg_id: None,
+                        in_reply_to: None,
+                        msg_type: json!({"type": "tick"}),
+                    },
+                },
+                true,
+            );
+        })
+        .run_with(Channel::new(frames), input, handle_echo);
+    let node = tokio::spawn(node);
+
+    let init = json!({"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]});
+    let echo = json!({"type": "echo", "msg_id": 2, "echo": "hello"});
+    for body in [init, echo] {
+        tx.send(json!({"src": "c1", "dest": "n1", "body": body}))
+            .await
+            .unwrap();
+    }
+    assert_eq!(written.recv().await.unwrap()["body"]["type"], "init_ok");
+    assert_eq!(written.recv().await.unwrap()["body"]["echo"], "hello");
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    drop(tx);
+
+    tokio::time::timeout(Duration::from_secs(5), node)
+        .await
+        .expect("node stops")
+        .unwrap()
+        .unwrap();
+    // Ticks still waiting in their batch were sent on the way out
+    let batched = written.recv().await.unwrap();
+    assert_eq!(batched["dest"], "n2");
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use symmetrical_octo_potato::protocol::{Inbound, MaelstromProtocol, Protocol};
+
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum ToyMessage {
+    Echo { echo: String },
+    EchoOk { echo: String },
+    ReadAll,
+    ReadAllOk { values: Vec<usize> },
+    Gossip(GossipPayload),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct GossipPayload {
+    values: Vec<usize>,
+}
+
+fn request(value: serde_json::Value) -> ToyRequest {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn requests_leave_out_replies() {
+    assert_eq!(ToyRequest::TYPES, ["echo", "read_all", "gossip"]);
+    assert!(matches!(
+        request(json!({"type": "echo", "echo": "hi"})),
+        ToyRequest::Echo { echo } if echo == "hi"
+    ));
+    assert!(matches!(
+        request(json!({"type": "gossip", "values": [1, 2]})),
+        ToyRequest::Gossip(GossipPayload { values }) if values == [1, 2]
+    ));
+    assert!(
+        serde_json::from_value::<ToyRequest>(json!({"type": "echo_ok", "echo": "hi"})).is_err()
+    );
+}
+
+#[test]
+fn replies_are_built_and_serialized_as_ok() {
+    let reply = ToyMessage::echo_ok("hi".to_string());
+    let value = serde_json::to_value(&reply).unwrap();
+    assert_eq!(value, json!({"type": "echo_ok", "echo": "hi"}));
+
+    let reply: ToyMessage = serde_json::from_value(value).unwrap();
+    assert!(matches!(reply, ToyMessage::EchoOk { echo } if echo == "hi"));
+    assert_eq!(
+        serde_json::to_value(ToyMessage::read_all_ok(vec![1])).unwrap(),
+        json!({"type": "read_all_ok", "values": [1]})
+    );
+}
+
+#[test]
+fn replies_answer_only_their_request() {
+    let echo = request(json!({"type": "echo", "echo": "hi"}));
+    let read_all = request(json!({"type": "read_all"}));
+    let gossip = request(json!({"type": "gossip", "values": []}));
+
+    let echo_ok = ToyMessage::echo_ok("hi".to_string());
+    assert!(echo_ok.answers(&echo));
+    assert!(!echo_ok.answers(&read_all));
+    assert!(ToyMessage::read_all_ok(vec![]).answers(&read_all));
+
+    // Requests answer nothing, and nothing answers a request without `Ok`
+    let requests = [echo, read_all, gossip.clone()];
+    let gossip_reply = ToyMessage::Gossip(GossipPayload { values: vec![] });
+    assert!(requests
+        .iter()
+        .all(|request| !gossip_reply.answers(request)));
+    assert!(!ToyMessage::read_all_ok(vec![]).answers(&gossip));
+    assert!(!echo_ok.answers(&gossip));
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use common::Channel;
+use serde_json::{json, Value};
+use std::{collections::BTreeSet, time::Duration};
+use symmetrical_octo_potato::{
+    dispatcher::Dispatcher,
+    message::{Body, ErrorCode, MaelstromError, Message},
+    sender::{Batching, Sender},
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+fn batching_sender() -> (Sender<Channel>, UnboundedReceiver<Value>) {
+    let (tx, rx) = unbounded_channel();
+    let mut sender = Sender::new(Channel::new(tx));
+    sender.set_node_id("n1");
+    sender.set_batching(
+        Batching {
+            max_size: 3,
+            max_delay: Duration::from_millis(100),
+        },
+        BTreeSet::from(["n2".to_string()]),
+    );
+    (sender, rx)
+}
+
+fn message(dest: &str, value: usize) -> Message<Value> {
+    Message {
+        src: "n1".to_string(),
+        dest: dest.to_string(),
+        body: Body {
+            msg_id: None,
+            in_reply_to: None,
+            msg_type: json!({"type": "gossip", "value": value}),
+        },
+    }
+}
+
+#[tokio::test]
+async fn batches_messages_to_peers() {
+    let (mut sender, mut rx) = batching_sender();
+    for value in 0..4 {
+        sender.send(message("n2", value), true).unwrap();
+    }
+    sender.send(message("c1", 4), true).unwrap();
+
+    let batch = rx.try_recv().unwrap();
+    assert_eq!(batch["dest"], "n2");
+    assert_eq!(batch["body"]["type"], "batch");
+    let values: Vec<_> = batch["body"]["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|message| message["body"]["value"].clone())
+        .collect();
+    assert_eq!(values, [0, 1, 2]);
+    assert_eq!(rx.try_recv().unwrap()["dest"], "c1");
+    assert!(rx.try_recv().is_err());
+
+    // The last message waits for the delay, and leaves alone
+    sender.flush_due().unwrap();
+    assert!(rx.try_recv().is_err());
+    tokio::time::sleep_until(sender.next_due().unwrap()).await;
+    sender.flush_due().unwrap();
+    let alone = rx.try_recv().unwrap();
+    assert_eq!(alone["body"]["value"], 3);
+    assert_eq!(alone["body"]["msg_id"], 3);
+    assert_eq!(sender.next_due(), None);
+}
+
+#[tokio::test]
+async fn dispatcher_unpacks_batches() {
+    let (mut sender, mut frames) = batching_sender();
+    let mut dispatcher = Dispatcher::new(sender.replies());
+    let mut gossip = dispatcher.register(&["gossip"]);
+    for value in 0..3 {
+        sender.send(message("n2", value), true).unwrap();
+    }
+
+    dispatcher.dispatch(frames.recv().await.unwrap());
+    for value in 0..3 {
+        assert_eq!(gossip.recv().await.unwrap()["body"]["value"], value);
+    }
+}
+
+fn rpc_sender() -> (Sender<Channel>, UnboundedReceiver<Value>) {
+    let (tx, rx) = unbounded_channel();
+    let mut sender = Sender::new(Channel::new(tx));
+    sender.set_node_id("n1");
+    (sender, rx)
+}
+
+fn reply_to(request: &Value, mut body: Value) -> Value {
+    body["in_reply_to"] = request["body"]["msg_id"].clone();
+    json!({"src": request["dest"], "dest": request["src"], "body": body})
+}
+
+#[tokio::test]
+async fn rpc_resolves_with_its_reply() {
+    let (mut sender, mut frames) = rpc_sender();
+    let replies = sender.replies();
+    let call = sender.rpc::<_, Value>("n2", json!({"type": "read"}));
+    let request = frames.recv().await.unwrap();
+    assert_eq!(request["body"]["type"], "read");
+
+    let reply = reply_to(&request, json!({"type": "read_ok", "value": 1}));
+    assert_eq!(replies.resolve(reply), None);
+    let reply = call.await.unwrap();
+    assert_eq!(reply.body.msg_type, json!({"type": "read_ok", "value": 1}));
+
+    // Messages nobody waits on are given back
+    let unsolicited = json!({"src": "n2", "dest": "n1", "body": {"type": "read"}});
+    assert_eq!(replies.resolve(unsolicited.clone()), Some(unsolicited));
+}
+
+#[tokio::test]
+async fn rpc_fails_with_error_replies() {
+    let (mut sender, mut frames) = rpc_sender();
+    let replies = sender.replies();
+    let call = sender.rpc::<_, Value>("n2", json!({"type": "read"}));
+    let request = frames.recv().await.unwrap();
+
+    let error = json!({"type": "error", "code": 20, "text": "key does not exist"});
+    assert_eq!(replies.resolve(reply_to(&request, error)), None);
+    let Err(error) = call.await else {
+        panic!("expected an error");
+    };
+    let error = error.downcast_ref::<MaelstromError>().unwrap();
+    assert_eq!(error.code, ErrorCode::KeyDoesNotExist);
+}
+
+#[tokio::test]
+async fn rpc_times_out_and_ignores_late_replies() {
+    let (mut sender, mut frames) = rpc_sender();
+    let replies = sender.replies();
+    let call =
+        sender.rpc_timeout::<_, Value>("n2", json!({"type": "read"}), Duration::from_millis(10));
+    let request = frames.recv().await.unwrap();
+
+    let Err(error) = call.await else {
+        panic!("expected an error");
+    };
+    let error = error.downcast_ref::<MaelstromError>().unwrap();
+    assert_eq!(error.code, ErrorCode::Timeout);
+    assert!(!error.is_definite());
+
+    let late = reply_to(&request, json!({"type": "read_ok", "value": 1}));
+    assert_eq!(replies.resolve(late.clone()), Some(late));
+}
+
+#[tokio::test]
+async fn dropped_rpc_stops_waiting() {
+    let (mut sender, mut frames) = rpc_sender();
+    let replies = sender.replies();
+    let call = sender.rpc::<_, Value>("n2", json!({"type": "read"}));
+    let request = frames.recv().await.unwrap();
+
+    drop(call);
+    let reply = reply_to(&request, json!({"type": "read_ok", "value": 1}));
+    assert_eq!(replies.resolve(reply.clone()), Some(reply));
+}
@@ -0,0 +1,928 @@
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeSet,
+    future::Future,
+    io::Write,
+    ops::{Deref, DerefMut},
+    sync::atomic::Ordering,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use symmetrical_octo_potato::{
+    checker::{self, Consistency},
+    crdt::{Crdt, GCounter},
+    dispatcher::QUEUE_CAPACITY,
+    history::{History, Outcome},
+    init_state::{Init, InitState, Initable},
+    log::Log,
+    membership::{self, Status, Update},
+    message::Message,
+    protocol::MaelstromProtocol,
+    sender::{Batching, Sender},
+    sim::{self, run_command, run_process, Client, Delivery, NetworkConfig, Sim, SimWriter},
+    traits::{replica::Replica, store::Store},
+    Node,
+};
+use tokio::sync::mpsc::Receiver;
+
+struct LogState {
+    values: Log<usize>,
+}
+
+impl Initable for LogState {
+    fn with_init(init: Init) -> Self {
+        Self {
+            values: Log::with_init(init),
+        }
+    }
+}
+
+impl Deref for LogState {
+    type Target = Log<usize>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.values
+    }
+}
+
+impl DerefMut for LogState {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.values
+    }
+}
+
+impl Store<usize> for LogState {}
+
+#[derive(Default)]
+struct CounterState {
+    counter: GCounter,
+}
+
+impl Initable for CounterState {
+    fn with_init(_: Init) -> Self {
+        Self::default()
+    }
+}
+
+impl Replica for CounterState {
+    type Crdt = GCounter;
+
+    fn crdt(&self) -> &GCounter {
+        &self.counter
+    }
+
+    fn crdt_mut(&mut self) -> &mut GCounter {
+        &mut self.counter
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum BroadcastMessage {
+    Broadcast { message: usize },
+    BroadcastOk,
+    Read,
+    ReadOk { messages: BTreeSet<usize> },
+}
+
+fn handle_broadcast(
+    input: &Message<BroadcastRequest>,
+    output: &Arc<Mutex<Sender<SimWriter>>>,
+    state: &Arc<Mutex<InitState<LogState>>>,
+) -> Result<()> {
+    match input.body.msg_type {
+        BroadcastRequest::Broadcast { message } => {
+            let _ = state.lock().unwrap().insert(&message);
+            output
+                .lock()
+                .unwrap()
+                .reply_to(input, BroadcastMessage::broadcast_ok())
+        }
+        BroadcastRequest::Read => {
+            let messages = state.lock().unwrap().values().copied().collect();
+            output
+                .lock()
+                .unwrap()
+                .reply_to(input, BroadcastMessage::read_ok(messages))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum CounterMessage {
+    Add { delta: usize },
+    AddOk,
+    Read,
+    ReadOk { value: usize },
+}
+
+fn handle_counter(
+    input: &Message<CounterRequest>,
+    output: &Arc<Mutex<Sender<SimWriter>>>,
+    state: &Arc<Mutex<InitState<LogState>>>,
+) -> Result<()> {
+    match input.body.msg_type {
+        CounterRequest::Add { delta } => {
+            let _ = state.lock().unwrap().insert(&delta);
+            output
+                .lock()
+                .unwrap()
+                .reply_to(input, CounterMessage::add_ok())
+        }
+        CounterRequest::Read => {
+            let value = state.lock().unwrap().values().sum();
+            output
+                .lock()
+                .unwrap()
+                .reply_to(input, CounterMessage::read_ok(value))
+        }
+    }
+}
+
+fn handle_crdt_counter(
+    input: &Message<CounterRequest>,
+    output: &Arc<Mutex<Sender<SimWriter>>>,
+    state: &Arc<Mutex<InitState<CounterState>>>,
+) -> Result<()> {
+    match input.body.msg_type {
+        CounterRequest::Add { delta } => {
+            let mut state = state.lock().unwrap();
+            let node = state.get_init().node_id.clone();
+            state.counter.increment(&node, delta);
+            std::mem::drop(state);
+            output
+                .lock()
+                .unwrap()
+                .reply_to(input, CounterMessage::add_ok())
+        }
+        CounterRequest::Read => {
+            let value = state.lock().unwrap().counter.value();
+            output
+                .lock()
+                .unwrap()
+                .reply_to(input, CounterMessage::read_ok(value))
+        }
+    }
+}
+
+fn faulty_network() -> NetworkConfig {
+    NetworkConfig {
+        latency: (Duration::from_millis(1), Duration::from_millis(10)),
+        drop_rate: 0.1,
+        duplicate_rate: 0.1,
+    }
+}
+
+/// Sends `read` to every node until `converged` holds for all their replies
+async fn eventually<T, F>(sim: &Sim, client: &Client, read: T, converged: F)
+where
+    T: Serialize + DeserializeOwned + Clone,
+    F: Fn(&T) -> bool,
+{
+    for _ in 0..100 {
+        let mut all = true;
+        for node in sim.node_ids() {
+            let reply = client.call(node, read.clone()).await;
+            all &= reply.is_ok_and(|reply| converged(&reply));
+        }
+        if all {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("nodes did not converge");
+}
+
+#[tokio::test]
+async fn broadcast_converges_after_partition() {
+    let mut sim = Sim::new(faulty_network());
+    sim.add_nodes(5, |writer, input| {
+        Node::<LogState, _>::new()
+            .with_gossip(Duration::from_millis(20))
+            .run_with(writer, input, handle_broadcast)
+    });
+    sim.init().await.unwrap();
+    let client = sim.client();
+    let nodes: Vec<_> = sim.node_ids().iter().cloned().collect();
+
+    sim.partition(&[&["n1", "n2"], &["n3", "n4", "n5"]]);
+    for message in 0..20 {
+        let node = &nodes[message % nodes.len()];
+        let _: BroadcastMessage = client
+            .call(node, BroadcastMessage::Broadcast { message })
+            .await
+            .unwrap();
+    }
+    sim.heal();
+
+    let expected: BTreeSet<usize> = (0..20).collect();
+    eventually(
+        &sim,
+        &client,
+        BroadcastMessage::Read,
+        |reply| matches!(reply, BroadcastMessage::ReadOk { messages } if *messages == expected),
+    )
+    .await;
+}
+
+/// Adds to the counter of three nodes started with `node`, over a network set
+/// up with `config`, then reads the sum from every node. The returned sim
+/// traced every message.
+async fn counter_converges<F, Fut>(config: NetworkConfig, node: F) -> Sim
+where
+    F: Fn(SimWriter, Receiver<Value>) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let mut sim = Sim::new(config);
+    sim.record_trace();
+    sim.add_nodes(3, node);
+    sim.init().await.unwrap();
+    let client = sim.client();
+    let nodes: Vec<_> = sim.node_ids().iter().cloned().collect();
+
+    for delta in 1..=30 {
+        let node = &nodes[delta % nodes.len()];
+        let _: CounterMessage = client
+            .call(node, CounterMessage::Add { delta })
+            .await
+            .unwrap();
+    }
+
+    let expected: usize = (1..=30).sum();
+    eventually(
+        &sim,
+        &client,
+        CounterMessage::Read,
+        |reply| matches!(reply, CounterMessage::ReadOk { value } if *value == expected),
+    )
+    .await;
+    sim
+}
+
+#[tokio::test]
+async fn counter_converges_under_faults() {
+    counter_converges(faulty_network(), |writer, input| {
+        Node::<LogState, _>::new()
+            .with_gossip(Duration::from_millis(20))
+            .run_with(writer, input, handle_counter)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn counter_converges_with_batching() {
+    let sim = counter_converges(faulty_network(), |writer, input| {
+        Node::<LogState, _>::new()
+            .with_gossip(Duration::from_millis(20))
+            .with_batching(Batching {
+                max_size: 8,
+                max_delay: Duration::from_millis(5),
+            })
+            .run_with(writer, input, handle_counter)
+    })
+    .await;
+
+    let batches = sim
+        .trace()
+        .iter()
+        .filter(|delivery| delivery.message["body"]["type"] == "batch")
+        .count();
+    assert!(batches > 0, "nodes sent no batch");
+}
+
+#[tokio::test]
+async fn crdt_counter_converges_under_faults() {
+    counter_converges(faulty_network(), |writer, input| {
+        Node::<CounterState, _>::new()
+            .with_gossip(Duration::from_millis(20))
+            .run_with(writer, input, handle_crdt_counter)
+    })
+    .await;
+}
+
+/// Broadcasts `count` messages over a lossy network and returns what was
+/// delivered, along with how many messages between nodes were lost
+fn lossy_broadcast(seed: u64, count: usize) -> (Vec<Delivery>, usize) {
+    sim::deterministic(seed, || async move {
+        let mut sim = Sim::new(faulty_network());
+        sim.record_trace();
+        sim.add_nodes(3, |writer, input| {
+            Node::<LogState, _>::new()
+                .with_gossip(Duration::from_millis(20))
+                .run_with(writer, input, handle_broadcast)
+        });
+        sim.init().await.unwrap();
+        let client = sim.client();
+        for message in 0..count {
+            let _: BroadcastMessage = client
+                .call("n1", BroadcastMessage::Broadcast { message })
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        (sim.trace(), sim.stats().dropped.load(Ordering::Relaxed))
+    })
+}
+
+#[test]
+fn same_seed_replays_the_same_run() {
+    let (trace, _) = lossy_broadcast(7, 10);
+    assert!(!trace.is_empty());
+    assert_eq!(trace, lossy_broadcast(7, 10).0);
+    assert_ne!(trace, lossy_broadcast(8, 10).0);
+}
+
+#[test]
+fn shrinks_to_the_smallest_failing_workload() {
+    // Stands in for a bug that only shows up once a message is lost
+    let fails = |count| lossy_broadcast(3, count).1 > 0;
+    assert!(fails(20));
+    let smallest = sim::shrink(20, fails);
+    assert!(fails(smallest));
+    assert!(!fails(smallest - 1));
+}
+
+/// Answers `init`, then never reads its input again
+async fn stalled_node(mut writer: SimWriter, mut input: Receiver<Value>) -> Result<()> {
+    let init = input.recv().await.unwrap();
+    let body = json!({"type": "init_ok", "in_reply_to": init["body"]["msg_id"]});
+    let reply = json!({"src": init["dest"], "dest": init["src"], "body": body});
+    writer.write_all(reply.to_string().as_bytes())?;
+    writer.flush()?;
+    std::future::pending().await
+}
+
+#[tokio::test]
+async fn stalled_node_does_not_hold_up_the_others() {
+    let mut sim = Sim::new(NetworkConfig::default());
+    sim.add_nodes(1, stalled_node);
+    sim.add_nodes(1, |writer, input| {
+        Node::<LogState, _>::new().run_with(writer, input, handle_broadcast)
+    });
+    sim.init().await.unwrap();
+
+    let flood = sim.client().with_timeout(Duration::from_millis(100));
+    for message in 0..2 * QUEUE_CAPACITY {
+        let flood = flood.clone();
+        tokio::spawn(async move {
+            let _ = flood
+                .call::<_, Value>("n1", BroadcastMessage::Broadcast { message })
+                .await;
+        });
+    }
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let reply: BroadcastMessage = sim
+        .client()
+        .with_timeout(Duration::from_millis(50))
+        .call("n2", BroadcastMessage::Read)
+        .await
+        .unwrap();
+    assert!(matches!(reply, BroadcastMessage::ReadOk { messages } if messages.is_empty()));
+}
+
+#[tokio::test]
+async fn runs_nodes_as_processes() {
+    let mut sim = Sim::new(NetworkConfig::default());
+    sim.add_nodes(2, |writer, input| {
+        run_process(env!("CARGO_BIN_EXE_echo").into(), writer, input)
+    });
+    sim.init().await.unwrap();
+    let client = sim.client();
+    for node in sim.node_ids() {
+        let reply: Value = client
+            .call(node, json!({"type": "echo", "echo": node}))
+            .await
+            .unwrap();
+        assert_eq!(reply, json!({"type": "echo_ok", "echo": node}));
+    }
+}
+
+#[tokio::test]
+async fn pn_counter_converges_after_partition() {
+    let mut sim = Sim::new(NetworkConfig::default());
+    sim.add_nodes(3, |writer, input| {
+        run_process(env!("CARGO_BIN_EXE_pn_counter").into(), writer, input)
+    });
+    sim.init().await.unwrap();
+    let client = sim.client();
+    let nodes: Vec<_> = sim.node_ids().iter().cloned().collect();
+
+    sim.partition(&[&["n1"], &["n2", "n3"]]);
+    let mut expected = 0;
+    for i in 0..12_i64 {
+        let delta = if i % 3 == 0 { -i } else { i };
+        expected += delta;
+        let node = &nodes[usize::try_from(i).unwrap() % nodes.len()];
+        let reply: Value = client
+            .call(node, json!({"type": "add", "delta": delta}))
+            .await
+            .unwrap();
+        assert_eq!(reply, json!({"type": "add_ok"}));
+    }
+    sim.heal();
+
+    eventually(&sim, &client, json!({"type": "read"}), |reply: &Value| {
+        *reply == json!({"type": "read_ok", "value": expected})
+    })
+    .await;
+}
+
+/// Adds to counters of the grow_only_counter binary, run with `backend`,
+/// while its nodes are partitioned, then reads each sum from every node
+async fn grow_only_counter_converges(backend: &'static str) {
+    let mut sim = Sim::new(NetworkConfig::default());
+    sim.add_nodes(3, |writer, input| {
+        let mut command = tokio::process::Command::new(env!("CARGO_BIN_EXE_grow_only_counter"));
+        command.env("COUNTER_BACKEND", backend);
+        run_command(command, writer, input)
+    });
+    sim.init().await.unwrap();
+    let client = sim.client();
+    let nodes: Vec<_> = sim.node_ids().iter().cloned().collect();
+    let keys = [None, Some("a"), Some("b")];
+
+    sim.partition(&[&["n1"], &["n2", "n3"]]);
+    for delta in 1..=12 {
+        let node = &nodes[delta % nodes.len()];
+        let key = keys[delta % 4 % keys.len()];
+        let reply: Value = client
+            .call(node, json!({"type": "add", "delta": delta, "key": key}))
+            .await
+            .unwrap();
+        assert_eq!(reply, json!({"type": "add_ok"}));
+    }
+    sim.heal();
+
+    for (i, key) in keys.into_iter().enumerate() {
+        let expected: usize = (1..=12).filter(|delta| delta % 4 % keys.len() == i).sum();
+        eventually(
+            &sim,
+            &client,
+            json!({"type": "read", "key": key}),
+            |reply: &Value| *reply == json!({"type": "read_ok", "value": expected}),
+        )
+        .await;
+    }
+}
+
+#[tokio::test]
+async fn grow_only_counter_converges_with_gossip() {
+    grow_only_counter_converges("gossip").await;
+}
+
+#[tokio::test]
+async fn grow_only_counter_converges_with_seq_kv() {
+    grow_only_counter_converges("seq-kv").await;
+}
+
+/// Runs transactions on the txn binary, run in `mode`, while its nodes are
+/// partitioned: every one of them must succeed without showing anomalies
+/// `consistency` prohibits
+async fn txn_is_totally_available(mode: &'static str, nodes: usize, consistency: Consistency) {
+    let mut sim = Sim::new(NetworkConfig::default());
+    sim.add_nodes(nodes, |writer, input| {
+        let mut command = tokio::process::Command::new(env!("CARGO_BIN_EXE_txn"));
+        command.env("TXN_MODE", mode);
+        run_command(command, writer, input)
+    });
+    sim.init().await.unwrap();
+    let client = sim.client();
+    let node_ids: Vec<_> = sim.node_ids().iter().cloned().collect();
+
+    sim.partition(&[&["n1"], &["n2", "n3"]]);
+    let history = History::default();
+    for i in 0..30 {
+        if i == 15 {
+            sim.heal();
+        }
+        let (node, key) = (&node_ids[i % node_ids.len()], i % 3);
+        let request = json!({
+            "type": "txn",
+            "txn": [["r", key, null], ["w", key, i], ["r", (key + 1) % 3, null]],
+        });
+        let reply = client.call::<_, Value>(node, request.clone());
+        let outcome = history.record("c1", node, request, reply).await;
+        assert!(matches!(outcome, Outcome::Ok { .. }), "{outcome:?}");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    for node in &node_ids {
+        let request =
+            json!({"type": "txn", "txn": [["r", 0, null], ["r", 1, null], ["r", 2, null]]});
+        let reply = client.call::<_, Value>(node, request.clone());
+        history.record("c1", node, request, reply).await;
+    }
+    checker::txn_rw_register(&history.operations(), consistency).unwrap();
+}
+
+#[tokio::test]
+async fn txn_is_totally_available_on_a_single_node() {
+    txn_is_totally_available("single-node", 1, Consistency::ReadCommitted).await;
+}
+
+#[tokio::test]
+async fn txn_is_totally_available_read_uncommitted() {
+    txn_is_totally_available("read-uncommitted", 3, Consistency::ReadUncommitted).await;
+}
+
+#[tokio::test]
+async fn txn_is_totally_available_read_committed() {
+    txn_is_totally_available("read-committed", 3, Consistency::ReadCommitted).await;
+}
+
+#[tokio::test]
+async fn failed_txn_leaves_no_writes_behind() {
+    let mut sim = Sim::new(NetworkConfig::default());
+    sim.add_nodes(1, |writer, input| {
+        let mut command = tokio::process::Command::new(env!("CARGO_BIN_EXE_txn"));
+        command.env("TXN_MODE", "read-uncommitted");
+        run_command(command, writer, input)
+    });
+    sim.init().await.unwrap();
+    let client = sim.client();
+
+    let txn = json!({"type": "txn", "txn": [["w", 1, 5], ["append", 1, 6]]});
+    assert!(client.call::<_, Value>("n1", txn).await.is_err());
+    let reply: Value = client
+        .call("n1", json!({"type": "txn", "txn": [["r", 1, null]]}))
+        .await
+        .unwrap();
+    assert_eq!(reply, json!({"type": "txn_ok", "txn": [["r", 1, null]]}));
+}
+
+#[tokio::test]
+async fn kafka_polls_ordered_offset_pairs() {
+    let mut sim = Sim::new(NetworkConfig::default());
+    sim.add_nodes(1, |writer, input| {
+        run_process(env!("CARGO_BIN_EXE_kafka").into(), writer, input)
+    });
+    sim.init().await.unwrap();
+    let client = sim.client();
+
+    for msg in [30, 10, 20] {
+        let reply: Value = client
+            .call("n1", json!({"type": "send", "key": "k", "msg": msg}))
+            .await
+            .unwrap();
+        assert_eq!(reply["type"], "send_ok");
+    }
+    let reply: Value = client
+        .call("n1", json!({"type": "poll", "offsets": {"k": 1}}))
+        .await
+        .unwrap();
+    assert_eq!(
+        reply,
+        json!({"type": "poll_ok", "msgs": {"k": [[1, 10], [2, 20]]}})
+    );
+}
+
+/// A `cas` taking an offset can time out after being applied, leaving nothing
+/// at that offset. The client takes offset 1 that way, behind the nodes' back.
+#[tokio::test]
+async fn kafka_polls_step_over_offsets_taken_by_timed_out_cas() {
+    let mut sim = Sim::new(NetworkConfig::default());
+    sim.add_nodes(2, |writer, input| {
+        run_process(env!("CARGO_BIN_EXE_kafka").into(), writer, input)
+    });
+    sim.init().await.unwrap();
+    let client = sim.client();
+    let send =
+        |node, msg| client.call::<_, Value>(node, json!({"type": "send", "key": "k", "msg": msg}));
+    let poll = |node| client.call::<_, Value>(node, json!({"type": "poll", "offsets": {"k": 0}}));
+
+    assert_eq!(send("n1", 10).await.unwrap()["offset"], 0);
+    let cas = json!({"type": "cas", "key": "next_offset/k", "from": 1, "to": 2});
+    let timed_out = client.clone().with_timeout(Duration::ZERO);
+    assert!(timed_out.call::<_, Value>("lin-kv", cas).await.is_err());
+    assert_eq!(send("n2", 11).await.unwrap()["offset"], 2);
+
+    // n2 may not have heard of offset 0 yet, n1 surely heard of offset 2
+    let expected = json!({"type": "poll_ok", "msgs": {"k": [[0, 10], [2, 11]]}});
+    assert_eq!(poll("n2").await.unwrap(), expected);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(poll("n1").await.unwrap(), expected);
+
+    // The skipped offset stays empty
+    assert_eq!(send("n1", 12).await.unwrap()["offset"], 3);
+}
+
+/// Sends to and polls the kafka binary from every node while they are
+/// partitioned, then polls each key from the start on every node
+#[tokio::test]
+async fn kafka_offsets_are_never_skipped() {
+    let mut sim = Sim::new(NetworkConfig::default());
+    sim.add_nodes(3, |writer, input| {
+        run_process(env!("CARGO_BIN_EXE_kafka").into(), writer, input)
+    });
+    sim.init().await.unwrap();
+    let client = sim.client();
+    let nodes: Vec<_> = sim.node_ids().iter().cloned().collect();
+    let keys = ["a", "b"];
+
+    sim.partition(&[&["n1"], &["n2", "n3"]]);
+    let history = History::default();
+    let mut consumed = [0u64; 2];
+    for i in 0..30 {
+        if i == 15 {
+            sim.heal();
+        }
+        let (node, key) = (&nodes[i / 3 % nodes.len()], i % keys.len());
+        let request = if i % 3 == 2 {
+            json!({"type": "poll", "offsets": {keys[key]: consumed[key]}})
+        } else {
+            json!({"type": "send", "key": keys[key], "msg": i})
+        };
+        let reply = client.call::<_, Value>(node, request.clone());
+        let outcome = history.record("c1", node, request, reply).await;
+        let Outcome::Ok { reply } = outcome else {
+            panic!("{outcome:?}");
+        };
+        if let Some(polled) = reply["msgs"][keys[key]].as_array() {
+            if let Some(last) = polled.last() {
+                consumed[key] = last[0].as_u64().unwrap() + 1;
+            }
+        }
+    }
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    for node in &nodes {
+        let request = json!({"type": "poll", "offsets": {"a": 0, "b": 0}});
+        let reply = client.call::<_, Value>(node, request.clone());
+        history.record("c1", node, request, reply).await;
+    }
+
+    checker::kafka(&history.operations()).unwrap();
+}
+
+#[test]
+fn lin_kv_stand_in_is_linearizable() {
+    let history = sim::deterministic(11, || async {
+        let mut sim = Sim::new(faulty_network());
+        let history = History::default();
+        let mut clients = Vec::new();
+        for process in 0..4 {
+            let client = sim.client();
+            let history = history.clone();
+            clients.push(tokio::spawn(async move {
+                for i in 0..20 {
+                    let request = match (process + i) % 3 {
+                        0 => json!({"type": "read", "key": i % 2}),
+                        1 => json!({"type": "write", "key": i % 2, "value": i}),
+                        _ => json!({"type": "cas", "key": i % 2, "from": i - 1, "to": i}),
+                    };
+                    let reply = client.call::<_, Value>("lin-kv", request.clone());
+                    history
+                        .record(&format!("c{process}"), "lin-kv", request, reply)
+                        .await;
+                }
+            }));
+        }
+        for client in clients {
+            client.await.unwrap();
+        }
+        history.operations()
+    });
+    assert_eq!(history.len(), 80);
+    assert_eq!(checker::lin_kv(&history), Ok(()));
+}
+
+#[test]
+fn gossip_stops_once_everything_is_acknowledged() {
+    sim::deterministic(5, || async {
+        let mut sim = Sim::new(NetworkConfig::default());
+        sim.add_nodes(3, |writer, input| {
+            Node::<LogState, _>::new()
+                .with_gossip(Duration::from_millis(20))
+                .run_with(writer, input, handle_broadcast)
+        });
+        sim.init().await.unwrap();
+        let client = sim.client();
+        for message in 0..10 {
+            let _: BroadcastMessage = client
+                .call("n1", BroadcastMessage::Broadcast { message })
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        sim.record_trace();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(sim.trace(), Vec::new());
+    });
+}
+
+#[test]
+fn gossip_backs_off_from_unreachable_peers() {
+    sim::deterministic(3, || async {
+        let mut sim = Sim::new(NetworkConfig::default());
+        sim.add_nodes(3, |writer, input| {
+            Node::<LogState, _>::new()
+                .with_gossip(Duration::from_millis(20))
+                .run_with(writer, input, handle_broadcast)
+        });
+        sim.init().await.unwrap();
+        let client = sim.client();
+
+        sim.partition(&[&["n1", "n2"]]);
+        let _: BroadcastMessage = client
+            .call("n1", BroadcastMessage::Broadcast { message: 1 })
+            .await
+            .unwrap();
+        let before = sim.stats().between_nodes.load(Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        // Retrying every 20ms would be 100 gossips from each of n1 and n2
+        let gossips = sim.stats().between_nodes.load(Ordering::Relaxed) - before;
+        assert!(gossips < 30, "{gossips} gossips to an unreachable node");
+
+        sim.heal();
+        eventually(
+            &sim,
+            &client,
+            BroadcastMessage::Read,
+            |reply| matches!(reply, BroadcastMessage::ReadOk { messages } if messages.contains(&1)),
+        )
+        .await;
+    });
+}
+
+#[tokio::test]
+async fn plumtree_converges_after_partition() {
+    let mut sim = Sim::new(faulty_network());
+    sim.add_nodes(5, |writer, input| {
+        Node::<LogState, _>::new()
+            .with_plumtree(Duration::from_millis(20))
+            .run_with(writer, input, handle_broadcast)
+    });
+    sim.init().await.unwrap();
+    let client = sim.client();
+    let nodes: Vec<_> = sim.node_ids().iter().cloned().collect();
+
+    sim.partition(&[&["n1", "n2"], &["n3", "n4", "n5"]]);
+    for message in 0..20 {
+        let node = &nodes[message % nodes.len()];
+        let _: BroadcastMessage = client
+            .call(node, BroadcastMessage::Broadcast { message })
+            .await
+            .unwrap();
+    }
+    sim.heal();
+
+    let expected: BTreeSet<usize> = (0..20).collect();
+    eventually(
+        &sim,
+        &client,
+        BroadcastMessage::Read,
+        |reply| matches!(reply, BroadcastMessage::ReadOk { messages } if *messages == expected),
+    )
+    .await;
+}
+
+/// Messages between nodes to spread 50 broadcasts among 10 fully connected
+/// nodes
+fn messages_between_nodes(plumtree: bool) -> usize {
+    sim::deterministic(1, || async move {
+        let mut sim = Sim::new(NetworkConfig::default());
+        sim.add_nodes(10, |writer, input| {
+            let node = Node::<LogState, _>::new();
+            let node = if plumtree {
+                node.with_plumtree(Duration::from_millis(20))
+            } else {
+                node.with_gossip(Duration::from_millis(20))
+            };
+            node.run_with(writer, input, handle_broadcast)
+        });
+        sim.init().await.unwrap();
+        let client = sim.client();
+        let nodes: Vec<_> = sim.node_ids().iter().cloned().collect();
+        for message in 0..50 {
+            let _: BroadcastMessage = client
+                .call(
+                    &nodes[message % nodes.len()],
+                    BroadcastMessage::Broadcast { message },
+                )
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let expected: BTreeSet<usize> = (0..50).collect();
+        eventually(
+            &sim,
+            &client,
+            BroadcastMessage::Read,
+            |reply| matches!(reply, BroadcastMessage::ReadOk { messages } if *messages == expected),
+        )
+        .await;
+        sim.stats().between_nodes.load(Ordering::Relaxed)
+    })
+}
+
+#[test]
+fn plumtree_sends_fewer_messages_than_gossip() {
+    let plumtree = messages_between_nodes(true);
+    let gossip = messages_between_nodes(false);
+    assert!(plumtree < gossip, "plumtree {plumtree}, gossip {gossip}");
+}
+
+/// Keeps the membership changes it is notified of
+struct MembersState {
+    changes: Vec<Update>,
+}
+
+impl Initable for MembersState {
+    fn with_init(_init: Init) -> Self {
+        Self {
+            changes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, MaelstromProtocol)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum MembersMessage {
+    Members,
+    MembersOk {
+        alive: BTreeSet<String>,
+        dead: BTreeSet<String>,
+        changes: Vec<Update>,
+    },
+}
+
+fn handle_members(
+    input: &Message<MembersRequest>,
+    output: &Arc<Mutex<Sender<SimWriter>>>,
+    state: &Arc<Mutex<InitState<MembersState>>>,
+) -> Result<()> {
+    let state = state.lock().unwrap();
+    let membership = state.membership();
+    let owned = |nodes: BTreeSet<&str>| nodes.into_iter().map(str::to_string).collect();
+    let reply = MembersMessage::members_ok(
+        owned(membership.alive()),
+        owned(membership.dead()),
+        state.changes.clone(),
+    );
+    output.lock().unwrap().reply_to(input, reply)
+}
+
+#[test]
+fn membership_detects_and_readmits_partitioned_nodes() {
+    sim::deterministic(9, || async {
+        let mut sim = Sim::new(faulty_network());
+        sim.add_nodes(5, |writer, input| {
+            Node::<MembersState, _>::new()
+                .with_membership(membership::Config {
+                    period: Duration::from_millis(50),
+                    ping_timeout: Duration::from_millis(20),
+                    indirect_probes: 2,
+                    suspicion_timeout: Duration::from_millis(300),
+                })
+                .with_service(&[], |mut rx, _output, state| async move {
+                    let mut changes = state.lock().unwrap().membership().subscribe();
+                    loop {
+                        tokio::select! {
+                            _ = rx.recv() => break,
+                            Ok(change) = changes.recv() => state.lock().unwrap().changes.push(change),
+                        }
+                    }
+                })
+                .run_with(writer, input, handle_members)
+        });
+        sim.init().await.unwrap();
+        let client = sim.client();
+
+        sim.partition(&[&["n1", "n2", "n3", "n4"]]);
+        let n5_dead = |reply: &MembersMessage| {
+            matches!(reply, MembersMessage::MembersOk { dead, changes, .. }
+                if dead.contains("n5")
+                    && changes.iter().any(|change| change.node == "n5" && change.status == Status::Suspected))
+        };
+        for node in ["n1", "n2", "n3", "n4"] {
+            let mut reply = client.call(node, MembersMessage::Members).await.unwrap();
+            for _ in 0..100 {
+                if n5_dead(&reply) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                reply = client.call(node, MembersMessage::Members).await.unwrap();
+            }
+            assert!(n5_dead(&reply), "{node} did not notice n5 is gone");
+        }
+
+        sim.heal();
+        let everyone: BTreeSet<String> = sim.node_ids().iter().cloned().collect();
+        eventually(
+            &sim,
+            &client,
+            MembersMessage::Members,
+            |reply| matches!(reply, MembersMessage::MembersOk { alive, .. } if *alive == everyone),
+        )
+        .await;
+    });
+}
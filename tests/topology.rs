@@ -0,0 +1,89 @@
+use std::collections::BTreeSet;
+use symmetrical_octo_potato::topology::{self, Graph};
+
+fn nodes(count: usize) -> BTreeSet<String> {
+    (1..=count).map(|i| format!("n{i}")).collect()
+}
+
+/// Maelstrom's suggestion for broadcast: a line
+fn line(nodes: &BTreeSet<String>) -> Graph {
+    let nodes: Vec<_> = nodes.iter().collect();
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let neighbors = [i.checked_sub(1), Some(i + 1)]
+                .into_iter()
+                .flatten()
+                .filter_map(|j| nodes.get(j).map(|neighbor| (*neighbor).clone()))
+                .collect();
+            ((*node).clone(), neighbors)
+        })
+        .collect()
+}
+
+fn assert_connected(graph: &Graph) {
+    let first = graph.keys().next().unwrap();
+    let mut reached = BTreeSet::from([first]);
+    let mut stack = vec![first];
+    while let Some(node) = stack.pop() {
+        for neighbor in &graph[node] {
+            assert!(graph[neighbor].contains(node), "{node} -> {neighbor} only");
+            if reached.insert(neighbor) {
+                stack.push(neighbor);
+            }
+        }
+    }
+    assert_eq!(reached.len(), graph.len(), "{graph:?}");
+}
+
+fn links(graph: &Graph) -> usize {
+    graph.values().map(BTreeSet::len).sum::<usize>() / 2
+}
+
+#[test]
+fn every_topology_connects_every_node() {
+    let nodes = nodes(25);
+    let suggested = line(&nodes);
+    for name in [
+        "maelstrom",
+        "mesh",
+        "spanning-tree",
+        "tree",
+        "tree:2",
+        "grid",
+        "random",
+        "random:4:7",
+    ] {
+        let graph = topology::from_name(name).unwrap().graph(&nodes, &suggested);
+        assert_eq!(graph.len(), nodes.len(), "{name}");
+        assert_connected(&graph);
+    }
+}
+
+#[test]
+fn topologies_have_the_expected_shape() {
+    let nodes = nodes(16);
+    let suggested = line(&nodes);
+    let graph = |name| topology::from_name(name).unwrap().graph(&nodes, &suggested);
+
+    assert_eq!(links(&graph("mesh")), 16 * 15 / 2);
+    assert_eq!(links(&graph("spanning-tree")), 15);
+    assert_eq!(links(&graph("tree:3")), 15);
+    assert!(graph("tree:3")
+        .values()
+        .all(|neighbors| neighbors.len() <= 4));
+    // 4 rows of 4: 3 links along each row and 3 along each column
+    assert_eq!(links(&graph("grid")), 24);
+    assert!(graph("random:3")
+        .values()
+        .all(|neighbors| neighbors.len() == 3));
+    assert_eq!(graph("random:3:1"), graph("random:3:1"));
+    assert_ne!(graph("random:3:1"), graph("random:3:2"));
+}
+
+#[test]
+fn rejects_unknown_topologies() {
+    assert!(topology::from_name("star").is_err());
+    assert!(topology::from_name("tree:many").is_err());
+}